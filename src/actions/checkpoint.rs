@@ -0,0 +1,140 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// How many processed logs are remembered for deduplication.
+const MAX_SEEN_LOGS: usize = 10_000;
+
+/// Identifies a log independently of when it was received.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct LogKey {
+    pub tx_hash: String,
+    pub log_index: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CheckpointState {
+    last_block: Option<u64>,
+    seen: VecDeque<LogKey>,
+    #[serde(skip)]
+    seen_set: HashSet<LogKey>,
+}
+
+/// Last processed block and recently processed logs, persisted on the encrypted mount so that
+/// event processing can resume after a restart without replaying side effects.
+pub struct Checkpoint {
+    path: PathBuf,
+    state: Mutex<CheckpointState>,
+}
+
+impl Checkpoint {
+    pub fn open(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut state: CheckpointState = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            CheckpointState::default()
+        };
+        state.seen_set = state.seen.iter().cloned().collect();
+        log::info!("Opened event checkpoint at block {:?}", state.last_block);
+        Ok(Self { path, state: Mutex::new(state) })
+    }
+
+    /// Writes a temporary file and renames it over the file, so that a crash mid-write leaves
+    /// the previous state rather than a truncated file that cannot be opened.
+    fn persist(&self, state: &CheckpointState) -> eyre::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(state)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub async fn last_block(&self) -> Option<u64> {
+        self.state.lock().await.last_block
+    }
+
+    pub async fn is_seen(&self, key: &LogKey) -> bool {
+        self.state.lock().await.seen_set.contains(key)
+    }
+
+    /// Marks a log as processed. Returns false if it was processed before.
+    pub async fn mark_seen(&self, key: LogKey, block_number: u64) -> eyre::Result<bool> {
+        let mut state = self.state.lock().await;
+        if !state.seen_set.insert(key.clone()) {
+            return Ok(false);
+        }
+        state.seen.push_back(key);
+        while state.seen.len() > MAX_SEEN_LOGS {
+            if let Some(oldest) = state.seen.pop_front() {
+                state.seen_set.remove(&oldest);
+            }
+        }
+        state.last_block = state.last_block.max(Some(block_number));
+        self.persist(&state)?;
+        Ok(true)
+    }
+
+    /// Forgets a processed log, e.g. because a reorg removed it. Returns false if it was not
+    /// processed (or has aged out of the deduplication window).
+    pub async fn unmark_seen(&self, key: &LogKey) -> eyre::Result<bool> {
+        let mut state = self.state.lock().await;
+        if !state.seen_set.remove(key) {
+            return Ok(false);
+        }
+        state.seen.retain(|seen| seen != key);
+        self.persist(&state)?;
+        Ok(true)
+    }
+
+    /// Records that every block up to and including `block_number` has been processed.
+    pub async fn advance(&self, block_number: u64) -> eyre::Result<()> {
+        let mut state = self.state.lock().await;
+        if state.last_block >= Some(block_number) {
+            return Ok(());
+        }
+        state.last_block = Some(block_number);
+        self.persist(&state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn checkpoint_dedup_test() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.json", cuid::cuid2()));
+        let checkpoint = Checkpoint::open(&path)?;
+        assert_eq!(checkpoint.last_block().await, None);
+
+        let key = LogKey { tx_hash: "0xabc".to_string(), log_index: 3 };
+        assert!(!checkpoint.is_seen(&key).await);
+        assert!(checkpoint.mark_seen(key.clone(), 10).await?);
+        assert!(!checkpoint.mark_seen(key.clone(), 10).await?);
+        assert!(checkpoint.is_seen(&key).await);
+        checkpoint.advance(12).await?;
+        checkpoint.advance(11).await?;
+        assert_eq!(checkpoint.last_block().await, Some(12));
+
+        // Replays after a restart are still deduplicated.
+        let checkpoint = Checkpoint::open(&path)?;
+        assert_eq!(checkpoint.last_block().await, Some(12));
+        assert!(!checkpoint.mark_seen(key.clone(), 10).await?);
+        assert!(
+            checkpoint.mark_seen(LogKey { tx_hash: "0xabc".to_string(), log_index: 4 }, 10).await?
+        );
+
+        // A reorged log can be processed again once it is unmarked.
+        assert!(checkpoint.unmark_seen(&key).await?);
+        assert!(!checkpoint.unmark_seen(&key).await?);
+        assert!(checkpoint.mark_seen(key, 10).await?);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
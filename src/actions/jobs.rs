@@ -0,0 +1,653 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use eyre::OptionExt;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{Mutex, Notify},
+    time::{sleep, Duration},
+};
+
+use super::nft::{complete_reaction, complete_redemption, fail_reaction, fail_redemption};
+use crate::{
+    db::{
+        client_db::{ClientDB, TokenOwner},
+        TeleportDB, TokenType,
+    },
+    policy::PolicyChecker,
+    twitter::{builder::TwitterBuilder, error::TwitterError},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RedemptionJob {
+    pub token_id: String,
+    pub address: String,
+    pub content: String,
+    pub policy: String,
+    /// Looked up by the job when unset.
+    #[serde(default)]
+    pub token_owner: Option<TokenOwner>,
+    /// Shared by the tokens of a batch redeem that post as the same account. They run one at a
+    /// time and post a single tweet.
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+/// A like or retweet of `tweet_id` on behalf of the token's creator.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReactionJob {
+    pub token_id: String,
+    pub token_type: TokenType,
+    pub x_id: String,
+    pub tweet_id: String,
+    pub policy: String,
+    /// Looked up by the job when unset.
+    #[serde(default)]
+    pub token_owner: Option<TokenOwner>,
+}
+
+/// A redemption side effect that must eventually happen once the on-chain redeem is final.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum JobKind {
+    PostRedeemedTweet(RedemptionJob),
+    ReactToTweet(ReactionJob),
+}
+
+impl JobKind {
+    pub fn token_id(&self) -> &str {
+        match self {
+            JobKind::PostRedeemedTweet(job) => &job.token_id,
+            JobKind::ReactToTweet(job) => &job.token_id,
+        }
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            JobKind::PostRedeemedTweet(job) => job.group.as_deref(),
+            JobKind::ReactToTweet(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    pub attempts: u32,
+    /// Unix timestamp (seconds) before which the job is not retried.
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    /// X id of the user whose reauthorization the job is waiting for.
+    #[serde(default)]
+    pub held_for: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_backoff_secs: i64,
+    pub max_backoff_secs: i64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self { max_attempts: 8, base_backoff_secs: 30, max_backoff_secs: 3600 }
+    }
+}
+
+impl RetryConfig {
+    /// Reads `JOB_MAX_ATTEMPTS`, `JOB_BASE_BACKOFF_SECS` and `JOB_MAX_BACKOFF_SECS`, falling back
+    /// to the defaults for any that are unset.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        Self {
+            max_attempts: var("JOB_MAX_ATTEMPTS").unwrap_or(default.max_attempts as i64) as u32,
+            base_backoff_secs: var("JOB_BASE_BACKOFF_SECS").unwrap_or(default.base_backoff_secs),
+            max_backoff_secs: var("JOB_MAX_BACKOFF_SECS").unwrap_or(default.max_backoff_secs),
+        }
+    }
+
+    /// Exponential backoff after the given number of failed attempts.
+    pub fn backoff_secs(&self, attempts: u32) -> i64 {
+        let exponent = attempts.saturating_sub(1).min(30);
+        self.base_backoff_secs.saturating_mul(1i64 << exponent).min(self.max_backoff_secs)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JobQueueState {
+    jobs: Vec<Job>,
+    dead: Vec<Job>,
+    #[serde(skip)]
+    running: HashSet<String>,
+}
+
+/// What [`JobQueue::cancel_redemption`] found for a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The queued or held job was removed before it ran.
+    Cancelled,
+    /// The job is running and can no longer be stopped.
+    Running,
+    /// The job was dead-lettered after failing.
+    AlreadyDone,
+    /// No job was queued, e.g. because it completed.
+    NotFound,
+}
+
+/// Job queue persisted as JSON on the encrypted mount so jobs survive enclave restarts.
+pub struct JobQueue {
+    path: PathBuf,
+    config: RetryConfig,
+    state: Mutex<JobQueueState>,
+    notify: Notify,
+}
+
+impl JobQueue {
+    pub fn open(path: impl AsRef<Path>, config: RetryConfig) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            JobQueueState::default()
+        };
+        log::info!(
+            "Opened job queue with {} pending and {} dead jobs",
+            state.jobs.len(),
+            state.dead.len()
+        );
+        Ok(Self { path, config, state: Mutex::new(state), notify: Notify::new() })
+    }
+
+    /// Writes a temporary file and renames it over the file, so that a crash mid-write leaves
+    /// the previous state rather than a truncated file that cannot be opened.
+    fn persist(&self, state: &JobQueueState) -> eyre::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(state)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Queues a job unless the same job is already queued or dead, so that a replayed event does
+    /// not run its side effects twice. Returns the job's id.
+    pub async fn enqueue(&self, kind: JobKind) -> eyre::Result<String> {
+        let mut state = self.state.lock().await;
+        if let Some(job) = state.jobs.iter().chain(&state.dead).find(|job| job.kind == kind) {
+            return Ok(job.id.clone());
+        }
+        let job = Job {
+            id: cuid::cuid2(),
+            kind,
+            attempts: 0,
+            next_attempt_at: chrono::Utc::now().timestamp(),
+            last_error: None,
+            held_for: None,
+        };
+        let id = job.id.clone();
+        state.jobs.push(job);
+        self.persist(&state)?;
+        drop(state);
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    /// Returns the jobs that are due and marks them as running.
+    pub async fn take_due(&self) -> Vec<Job> {
+        let now = chrono::Utc::now().timestamp();
+        let mut state = self.state.lock().await;
+        let mut busy_groups = state
+            .jobs
+            .iter()
+            .filter(|job| state.running.contains(&job.id))
+            .filter_map(|job| job.kind.group().map(str::to_string))
+            .collect::<HashSet<_>>();
+        let due = state
+            .jobs
+            .iter()
+            .filter(|job| {
+                job.next_attempt_at <= now &&
+                    job.held_for.is_none() &&
+                    !state.running.contains(&job.id)
+            })
+            .filter(|job| {
+                job.kind.group().map_or(true, |group| busy_groups.insert(group.to_string()))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        state.running.extend(due.iter().map(|job| job.id.clone()));
+        due
+    }
+
+    pub async fn complete(&self, id: &str) -> eyre::Result<()> {
+        let mut state = self.state.lock().await;
+        state.running.remove(id);
+        state.jobs.retain(|job| job.id != id);
+        self.persist(&state)
+    }
+
+    /// Records a failed attempt and schedules a retry, or moves the job to the dead-letter list
+    /// once it has used up its attempts. Returns the job if it was dead-lettered.
+    pub async fn fail(&self, id: &str, error: String) -> eyre::Result<Option<Job>> {
+        let mut state = self.state.lock().await;
+        state.running.remove(id);
+        let index = state
+            .jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| eyre::eyre!("Job not found"))?;
+        let job = &mut state.jobs[index];
+        job.attempts += 1;
+        job.last_error = Some(error);
+        let dead = if job.attempts >= self.config.max_attempts {
+            let job = state.jobs.remove(index);
+            state.dead.push(job.clone());
+            Some(job)
+        } else {
+            job.next_attempt_at =
+                chrono::Utc::now().timestamp() + self.config.backoff_secs(job.attempts);
+            None
+        };
+        self.persist(&state)?;
+        Ok(dead)
+    }
+
+    /// Pushes a running job back to `at` without counting an attempt, for failures that say when
+    /// to try again such as an exhausted rate limit.
+    pub async fn reschedule(&self, id: &str, at: i64, error: String) -> eyre::Result<()> {
+        let mut state = self.state.lock().await;
+        state.running.remove(id);
+        let job = state
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| eyre::eyre!("Job not found"))?;
+        job.next_attempt_at = at;
+        job.last_error = Some(error);
+        self.persist(&state)
+    }
+
+    /// Parks a running job until the user with `x_id` logs in again, without counting an attempt.
+    pub async fn hold(&self, id: &str, x_id: String, error: String) -> eyre::Result<()> {
+        let mut state = self.state.lock().await;
+        state.running.remove(id);
+        let job = state
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| eyre::eyre!("Job not found"))?;
+        job.held_for = Some(x_id);
+        job.last_error = Some(error);
+        self.persist(&state)
+    }
+
+    pub async fn held_jobs(&self, x_id: &str) -> Vec<Job> {
+        let state = self.state.lock().await;
+        state.jobs.iter().filter(|job| job.held_for.as_deref() == Some(x_id)).cloned().collect()
+    }
+
+    /// Makes the jobs held for `x_id` due again. Returns how many were released.
+    pub async fn release_held(&self, x_id: &str) -> eyre::Result<usize> {
+        let mut state = self.state.lock().await;
+        let now = chrono::Utc::now().timestamp();
+        let mut released = 0;
+        for job in state.jobs.iter_mut().filter(|job| job.held_for.as_deref() == Some(x_id)) {
+            job.held_for = None;
+            job.next_attempt_at = now;
+            released += 1;
+        }
+        if released > 0 {
+            self.persist(&state)?;
+            drop(state);
+            self.notify.notify_one();
+        }
+        Ok(released)
+    }
+
+    /// Moves a job straight to the dead-letter list, for failures that retrying cannot fix.
+    pub async fn dead_letter(&self, id: &str, error: String) -> eyre::Result<Job> {
+        let mut state = self.state.lock().await;
+        state.running.remove(id);
+        let index = state
+            .jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| eyre::eyre!("Job not found"))?;
+        let mut job = state.jobs.remove(index);
+        job.attempts += 1;
+        job.last_error = Some(error);
+        state.dead.push(job.clone());
+        self.persist(&state)?;
+        Ok(job)
+    }
+
+    /// Removes the queued redemption job for `token_id`, unless it is already running.
+    pub async fn cancel_redemption(&self, token_id: &str) -> eyre::Result<CancelOutcome> {
+        let mut state = self.state.lock().await;
+        let JobQueueState { jobs, dead, running } = &mut *state;
+        let before = jobs.len();
+        jobs.retain(|job| job.kind.token_id() != token_id || running.contains(&job.id));
+        if jobs.len() != before {
+            self.persist(&state)?;
+            return Ok(CancelOutcome::Cancelled);
+        }
+        if jobs.iter().any(|job| job.kind.token_id() == token_id) {
+            Ok(CancelOutcome::Running)
+        } else if dead.iter().any(|job| job.kind.token_id() == token_id) {
+            Ok(CancelOutcome::AlreadyDone)
+        } else {
+            Ok(CancelOutcome::NotFound)
+        }
+    }
+
+    pub async fn dead_jobs(&self) -> Vec<Job> {
+        self.state.lock().await.dead.clone()
+    }
+
+    /// Moves dead jobs (all of them, or only the one with `id`) back onto the queue with a fresh
+    /// attempt budget.
+    pub async fn redrive_dead(&self, id: Option<String>) -> eyre::Result<Vec<Job>> {
+        let mut state = self.state.lock().await;
+        let (redriven, dead): (Vec<_>, Vec<_>) = std::mem::take(&mut state.dead)
+            .into_iter()
+            .partition(|job| id.as_ref().map_or(true, |id| &job.id == id));
+        state.dead = dead;
+        let now = chrono::Utc::now().timestamp();
+        for job in redriven.iter() {
+            state.jobs.push(Job { attempts: 0, next_attempt_at: now, ..job.clone() });
+        }
+        self.persist(&state)?;
+        drop(state);
+        self.notify.notify_one();
+        Ok(redriven)
+    }
+}
+
+pub async fn run_job_worker<A: TeleportDB>(
+    queue: Arc<JobQueue>,
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    twitter_builder: TwitterBuilder,
+    policy_checker: Arc<dyn PolicyChecker>,
+) {
+    loop {
+        for job in queue.take_due().await {
+            let queue = queue.clone();
+            let db = db.clone();
+            let client_db = client_db.clone();
+            let twitter_builder = twitter_builder.clone();
+            let policy_checker = policy_checker.clone();
+            tokio::spawn(async move {
+                let result = match job.kind.clone() {
+                    JobKind::PostRedeemedTweet(redemption_job) => {
+                        complete_redemption(
+                            db.clone(),
+                            client_db.clone(),
+                            twitter_builder,
+                            policy_checker,
+                            redemption_job,
+                        )
+                        .await
+                    }
+                    JobKind::ReactToTweet(reaction_job) => {
+                        complete_reaction(
+                            db.clone(),
+                            client_db.clone(),
+                            twitter_builder,
+                            reaction_job,
+                        )
+                        .await
+                    }
+                };
+                let outcome = match result {
+                    Ok(()) => queue.complete(&job.id).await.map(|_| None),
+                    Err(e) => match e.downcast_ref::<TwitterError>() {
+                        Some(TwitterError::AuthRevoked) => {
+                            hold_for_reauth(&db, &queue, &job, e.to_string()).await.map(|_| None)
+                        }
+                        Some(TwitterError::RateLimited { reset }) => {
+                            log::warn!("Job {} is rate limited until {}", job.id, reset);
+                            queue.reschedule(&job.id, *reset, e.to_string()).await.map(|_| None)
+                        }
+                        Some(error) if !error.is_retryable() => {
+                            log::error!("Job {} failed permanently: {:?}", job.id, e);
+                            queue.dead_letter(&job.id, e.to_string()).await.map(Some)
+                        }
+                        _ => {
+                            log::error!(
+                                "Job {} failed (attempt {}): {:?}",
+                                job.id,
+                                job.attempts + 1,
+                                e
+                            );
+                            queue.fail(&job.id, e.to_string()).await
+                        }
+                    },
+                };
+                match outcome {
+                    Ok(Some(dead_job)) => {
+                        log::error!("Job {} moved to the dead-letter list", dead_job.id);
+                        if let Err(e) = on_dead_job(db, client_db, dead_job).await {
+                            log::error!("Failed to record dead job: {:?}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::error!("Failed to update job queue: {:?}", e),
+                }
+            });
+        }
+        tokio::select! {
+            _ = sleep(POLL_INTERVAL) => {}
+            _ = queue.notify.notified() => {}
+        }
+    }
+}
+
+/// Flags the job's user as needing to log in again and parks the job until they do.
+async fn hold_for_reauth<A: TeleportDB>(
+    db: &Arc<Mutex<A>>,
+    queue: &JobQueue,
+    job: &Job,
+    error: String,
+) -> eyre::Result<()> {
+    let mut db_lock = db.lock().await;
+    let x_id = match &job.kind {
+        JobKind::PostRedeemedTweet(redemption_job) => db_lock
+            .get_user_by_address(redemption_job.address.clone())?
+            .x_id
+            .ok_or_eyre("User has no x_id")?,
+        JobKind::ReactToTweet(reaction_job) => reaction_job.x_id.clone(),
+    };
+    db_lock.set_reauth_required(x_id.clone(), true)?;
+    drop(db_lock);
+    log::warn!("Holding job {} until user {} logs in again", job.id, x_id);
+    queue.hold(&job.id, x_id, error).await
+}
+
+async fn on_dead_job<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    job: Job,
+) -> eyre::Result<()> {
+    let error = job.last_error.unwrap_or_default();
+    match job.kind {
+        JobKind::PostRedeemedTweet(redemption_job) => {
+            fail_redemption(db, client_db, redemption_job, error).await
+        }
+        JobKind::ReactToTweet(reaction_job) => {
+            fail_reaction(db, client_db, reaction_job, error).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_job_kind() -> JobKind {
+        JobKind::PostRedeemedTweet(RedemptionJob {
+            token_id: "1".to_string(),
+            address: "0x36e7Fda8CC503D5Ec7729A42eb86EF02Af315Bf9".to_string(),
+            content: "gm".to_string(),
+            policy: "policy".to_string(),
+            token_owner: Some(TokenOwner {
+                user_id: "user".to_string(),
+                twitter_user_name: "@user".to_string(),
+            }),
+            group: None,
+        })
+    }
+
+    #[test]
+    fn backoff_test() {
+        let config = RetryConfig { max_attempts: 5, base_backoff_secs: 10, max_backoff_secs: 60 };
+        assert_eq!(config.backoff_secs(1), 10);
+        assert_eq!(config.backoff_secs(2), 20);
+        assert_eq!(config.backoff_secs(3), 40);
+        assert_eq!(config.backoff_secs(4), 60);
+        assert_eq!(config.backoff_secs(100), 60);
+    }
+
+    #[tokio::test]
+    async fn job_queue_dead_letter_test() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jobs-{}.json", cuid::cuid2()));
+        let config = RetryConfig { max_attempts: 2, base_backoff_secs: 0, max_backoff_secs: 0 };
+        let queue = JobQueue::open(&path, config.clone())?;
+        let id = queue.enqueue(test_job_kind()).await?;
+        // Replayed events do not queue a job twice.
+        assert_eq!(queue.enqueue(test_job_kind()).await?, id);
+
+        assert_eq!(queue.take_due().await.len(), 1);
+        // Running jobs are not handed out twice.
+        assert!(queue.take_due().await.is_empty());
+        assert!(queue.fail(&id, "boom".to_string()).await?.is_none());
+        assert_eq!(queue.take_due().await.len(), 1);
+        let dead = queue.fail(&id, "boom".to_string()).await?.unwrap();
+        assert_eq!(dead.attempts, 2);
+        assert!(queue.take_due().await.is_empty());
+        assert_eq!(queue.enqueue(test_job_kind()).await?, id);
+        assert!(queue.take_due().await.is_empty());
+
+        // Jobs survive a restart.
+        let queue = JobQueue::open(&path, config)?;
+        assert_eq!(queue.dead_jobs().await.len(), 1);
+        assert_eq!(queue.redrive_dead(None).await?.len(), 1);
+        let due = queue.take_due().await;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 0);
+        queue.complete(&id).await?;
+        assert!(queue.dead_jobs().await.is_empty());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_queue_hold_test() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jobs-{}.json", cuid::cuid2()));
+        let queue = JobQueue::open(&path, RetryConfig::default())?;
+        let id = queue.enqueue(test_job_kind()).await?;
+
+        assert_eq!(queue.take_due().await.len(), 1);
+        queue.hold(&id, "42".to_string(), "revoked".to_string()).await?;
+        assert!(queue.take_due().await.is_empty());
+        assert_eq!(queue.held_jobs("42").await.len(), 1);
+        assert!(queue.held_jobs("43").await.is_empty());
+
+        // Holds survive a restart.
+        let queue = JobQueue::open(&path, RetryConfig::default())?;
+        assert_eq!(queue.release_held("43").await?, 0);
+        assert_eq!(queue.release_held("42").await?, 1);
+        let due = queue.take_due().await;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 0);
+        assert!(queue.held_jobs("42").await.is_empty());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_queue_reschedule_test() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jobs-{}.json", cuid::cuid2()));
+        let config = RetryConfig { max_attempts: 2, base_backoff_secs: 0, max_backoff_secs: 0 };
+        let queue = JobQueue::open(&path, config)?;
+        let id = queue.enqueue(test_job_kind()).await?;
+
+        // A rate-limited job waits for the reset without using up an attempt.
+        assert_eq!(queue.take_due().await.len(), 1);
+        let reset = chrono::Utc::now().timestamp() + 60;
+        queue.reschedule(&id, reset, "rate limited".to_string()).await?;
+        assert!(queue.take_due().await.is_empty());
+        queue.reschedule(&id, 0, "rate limited".to_string()).await?;
+        let due = queue.take_due().await;
+        assert_eq!(due[0].attempts, 0);
+
+        // Permanent failures skip the remaining attempts.
+        let dead = queue.dead_letter(&id, "duplicate".to_string()).await?;
+        assert_eq!(dead.attempts, 1);
+        assert_eq!(queue.dead_jobs().await.len(), 1);
+        assert!(queue.take_due().await.is_empty());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_queue_cancel_test() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jobs-{}.json", cuid::cuid2()));
+        let config = RetryConfig { max_attempts: 1, base_backoff_secs: 0, max_backoff_secs: 0 };
+        let queue = JobQueue::open(&path, config)?;
+        assert_eq!(queue.cancel_redemption("1").await?, CancelOutcome::NotFound);
+
+        queue.enqueue(test_job_kind()).await?;
+        assert_eq!(queue.cancel_redemption("1").await?, CancelOutcome::Cancelled);
+        assert!(queue.take_due().await.is_empty());
+
+        let id = queue.enqueue(test_job_kind()).await?;
+        assert_eq!(queue.take_due().await.len(), 1);
+        assert_eq!(queue.cancel_redemption("1").await?, CancelOutcome::Running);
+        queue.fail(&id, "boom".to_string()).await?;
+        assert_eq!(queue.cancel_redemption("1").await?, CancelOutcome::AlreadyDone);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_queue_group_test() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("jobs-{}.json", cuid::cuid2()));
+        let config = RetryConfig { max_attempts: 2, base_backoff_secs: 0, max_backoff_secs: 0 };
+        let queue = JobQueue::open(&path, config)?;
+        let grouped = |token_id: &str| match test_job_kind() {
+            JobKind::PostRedeemedTweet(job) => JobKind::PostRedeemedTweet(RedemptionJob {
+                token_id: token_id.to_string(),
+                group: Some("0xabc:0xdef".to_string()),
+                ..job
+            }),
+            kind => kind,
+        };
+        let first = queue.enqueue(grouped("1")).await?;
+        queue.enqueue(grouped("2")).await?;
+        queue.enqueue(test_job_kind()).await?;
+
+        // Jobs of one group run one at a time; ungrouped jobs are not held back.
+        let due = queue.take_due().await;
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].id, first);
+        assert!(queue.take_due().await.is_empty());
+        queue.complete(&first).await?;
+        let due = queue.take_due().await;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].kind.token_id(), "2");
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
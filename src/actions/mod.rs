@@ -1,2 +1,6 @@
+pub mod checkpoint;
+pub mod jobs;
 pub mod nft;
+pub mod subscriber;
+pub mod tx_manager;
 pub mod wallet;
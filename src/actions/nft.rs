@@ -3,24 +3,35 @@ use std::{str::FromStr, sync::Arc};
 use alloy::{
     hex::ToHexExt,
     primitives::{keccak256, Address, FixedBytes, Uint},
-    providers::{Provider, ProviderBuilder, WsConnect},
-    rpc::types::{BlockNumberOrTag, Filter},
+    providers::{Provider, ProviderBuilder},
     sol,
-    sol_types::SolEventInterface,
+    sol_types::{SolCall, SolEventInterface},
 };
-use eyre::OptionExt;
-use futures_util::stream::StreamExt;
-use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, oneshot, Mutex};
+use eyre::{OptionExt, WrapErr};
+use serde::Serialize;
+use tokio::sync::Mutex;
 use NFT::NFTEvents;
 
-use self::NFT::{NewTokenData, RedeemTweet, Transfer};
+use self::NFT::{NewTokenData, RedeemLike, RedeemTweet, Transfer};
 
-use super::wallet::WalletProvider;
+use super::{
+    jobs::{CancelOutcome, JobKind, JobQueue, ReactionJob, RedemptionJob},
+    tx_manager::{PendingTx, TxManager},
+};
 use crate::{
-    db::{client_db::ClientDB, TeleportDB},
-    oai,
-    twitter::{builder::TwitterBuilder, tweet::Tweet},
+    content::{RedeemContent, ReferencedTweet},
+    db::{
+        client_db::{ClientDB, TokenOwner},
+        Redemption, RedemptionStatus, TeleportDB, TokenType, User,
+    },
+    media_fetch::{self, FetchConfig},
+    policy::{
+        PolicyChecker, PolicyMedia, PolicyRequest, PolicyVerdict, TweetContext, TweetRelation,
+    },
+    twitter::{
+        builder::{TwitterBuilder, TwitterClient},
+        media::{sniff_media_type, validate_media, MAX_IMAGES},
+    },
 };
 
 sol!(
@@ -29,10 +40,50 @@ sol!(
     "abi/nft.json"
 );
 
-#[derive(Deserialize)]
-struct TweetContent {
-    text: String,
-    media_url: Option<String>,
+sol!(
+    #[sol(rpc)]
+    Redeem,
+    "abi/redeem.json"
+);
+
+/// The policy checker's view of the tweet a redemption replies to or quotes.
+fn tweet_relation(referenced: ReferencedTweet) -> (TweetRelation, &str) {
+    match referenced {
+        ReferencedTweet::Reply(tweet_id) => (TweetRelation::Reply, tweet_id),
+        ReferencedTweet::Quote(tweet_id) => (TweetRelation::Quote, tweet_id),
+    }
+}
+
+/// Builds a client that acts as `user`, preferring their OAuth 2.0 tokens. Expiring tokens are
+/// refreshed under the database lock, since X invalidates the old refresh token when it rotates.
+pub async fn user_client<'a, A: TeleportDB>(
+    db: &Mutex<A>,
+    twitter_builder: &'a TwitterBuilder,
+    user: User,
+) -> eyre::Result<TwitterClient<'a>> {
+    let Some(tokens) = user.oauth2_tokens else {
+        let access_tokens = user.access_tokens.ok_or_eyre("User has no access tokens")?;
+        return Ok(twitter_builder.with_auth(access_tokens.into()));
+    };
+    if !tokens.needs_refresh() {
+        return Ok(twitter_builder.with_oauth2_token(tokens.access_token));
+    }
+    let x_id = user.x_id.ok_or_eyre("User has no x_id")?;
+    let mut db_lock = db.lock().await;
+    // Another job may have refreshed the tokens while this one waited for the lock.
+    let tokens = db_lock
+        .get_user_by_x_id(x_id.clone())?
+        .oauth2_tokens
+        .ok_or_eyre("User has no OAuth 2.0 tokens")?;
+    let tokens = if tokens.needs_refresh() {
+        let refreshed = twitter_builder.oauth2_refresh(&tokens).await?;
+        db_lock.set_oauth2_tokens(x_id, refreshed.clone())?;
+        refreshed
+    } else {
+        tokens
+    };
+    drop(db_lock);
+    Ok(twitter_builder.with_oauth2_token(tokens.access_token))
 }
 
 pub fn get_nft_address() -> eyre::Result<Address> {
@@ -40,6 +91,12 @@ pub fn get_nft_address() -> eyre::Result<Address> {
     Ok(Address::from_str(&nft_address)?)
 }
 
+/// Address of the contract that redeems several tokens with the same content in one call.
+pub fn get_batch_redeem_address() -> eyre::Result<Address> {
+    let batch_redeem_address = std::env::var("BATCH_REDEEM_ADDRESS")?;
+    Ok(Address::from_str(&batch_redeem_address)?)
+}
+
 pub async fn get_token_id(rpc_url: String, nft_id: String) -> eyre::Result<String> {
     let provider = ProviderBuilder::new().on_http(rpc_url.parse()?);
     let nft_address = get_nft_address()?;
@@ -49,128 +106,519 @@ pub async fn get_token_id(rpc_url: String, nft_id: String) -> eyre::Result<Strin
     Ok(token_id.to_string())
 }
 
-pub async fn subscribe_to_nft_events<A: TeleportDB>(
+pub async fn get_owner_of(rpc_url: String, token_id: String) -> eyre::Result<Address> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.parse()?);
+    let nft = NFT::new(get_nft_address()?, provider);
+    let owner = nft.ownerOf(Uint::from_str(&token_id)?).call().await?._0;
+    Ok(owner)
+}
+
+pub async fn handle_event<A: TeleportDB>(
     db: Arc<Mutex<A>>,
-    twitter_builder: TwitterBuilder,
-    ws_rpc_url: String,
-    database_url: String,
+    client_db: ClientDB,
+    job_queue: Arc<JobQueue>,
+    rpc_url: String,
+    tx_hash: Option<FixedBytes<32>>,
+    event: NFTEvents,
 ) -> eyre::Result<()> {
-    let ws = WsConnect::new(ws_rpc_url);
-    let provider = ProviderBuilder::new().on_ws(ws).await?;
-    let nft_address = get_nft_address()?;
+    match event {
+        NFTEvents::RedeemTweet(redeem) => handle_redeem_tweet(job_queue, tx_hash, redeem)
+            .await
+            .wrap_err("Error handling RedeemTweet event"),
+        NFTEvents::RedeemLike(redeem) => {
+            handle_redeem_like(db, job_queue, rpc_url, tx_hash, redeem)
+                .await
+                .wrap_err("Error handling RedeemLike event")
+        }
+        NFTEvents::NewTokenData(new_token_data) => {
+            handle_new_token_data(db, client_db, tx_hash, new_token_data)
+                .await
+                .wrap_err("Error handling NewTokenData event")
+        }
+        NFTEvents::Transfer(transfer) => {
+            handle_transfer(client_db, transfer).await.wrap_err("Error handling Transfer event")
+        }
+        _ => Ok(()),
+    }
+}
 
-    let filter = Filter::new().address(nft_address).from_block(BlockNumberOrTag::Latest);
+/// Checks redeem content against the token's policy, with the tweet it references and its media.
+/// Returns the verdict and the hashes of the fetched media.
+pub async fn check_redeem_content<A: TeleportDB>(
+    db: &Mutex<A>,
+    twitter_builder: &TwitterBuilder,
+    policy_checker: &dyn PolicyChecker,
+    address: &str,
+    tweet_content: &RedeemContent,
+    policy: String,
+) -> eyre::Result<(PolicyVerdict, Vec<String>)> {
+    let mut request = PolicyRequest::new(tweet_content.text.clone(), policy);
+    if let Some(context) = get_tweet_context(db, twitter_builder, address, tweet_content).await? {
+        request = request.with_context(context);
+    }
+    let (media, media_hashes) = fetch_media(tweet_content).await?;
+    let verdict = policy_checker.check(&request.with_media(media)).await?;
+    Ok((verdict, media_hashes))
+}
 
-    log::info!("Subscribed to events for contract at: {}", nft_address.to_string());
+/// The tweet that redeem content replies to or quotes, read with the X account of `address`.
+pub async fn get_tweet_context<A: TeleportDB>(
+    db: &Mutex<A>,
+    twitter_builder: &TwitterBuilder,
+    address: &str,
+    tweet_content: &RedeemContent,
+) -> eyre::Result<Option<TweetContext>> {
+    let Some((relation, tweet_id)) = tweet_content.referenced_tweet()?.map(tweet_relation) else {
+        return Ok(None);
+    };
+    let db_lock = db.lock().await;
+    let user = db_lock.get_user_by_address(address.to_string())?;
+    drop(db_lock);
+    let client = user_client(db, twitter_builder, user).await?;
+    let referenced = client.get_tweet(tweet_id).await?;
+    Ok(Some(TweetContext { relation, text: referenced.text }))
+}
 
-    let sub = provider.subscribe_logs(&filter).await?;
-    let mut stream = sub.into_stream();
+/// Queues the redemption as soon as the redeem is final. The token is burned by then, so
+/// everything that can fail runs in the retrying job (see [`complete_redemption`]).
+async fn handle_redeem_tweet(
+    job_queue: Arc<JobQueue>,
+    tx_hash: Option<FixedBytes<32>>,
+    redeem: RedeemTweet,
+) -> eyre::Result<()> {
+    job_queue
+        .enqueue(JobKind::PostRedeemedTweet(RedemptionJob {
+            token_id: redeem.tokenId.to_string(),
+            address: redeem.addr.to_string(),
+            content: redeem.content,
+            policy: redeem.policy,
+            token_owner: None,
+            // The tokens of a batch redeem that post as the same account share one tweet.
+            group: tx_hash
+                .map(|tx_hash| format!("{}:{}", tx_hash.encode_hex_with_prefix(), redeem.addr)),
+        }))
+        .await?;
+    Ok(())
+}
 
-    let client_db = ClientDB::new(database_url);
+/// The token's creator, looked up in the index for jobs that were queued without it.
+async fn resolve_token_owner(
+    client_db: &ClientDB,
+    token_id: &str,
+    token_owner: Option<TokenOwner>,
+) -> eyre::Result<TokenOwner> {
+    match token_owner {
+        Some(token_owner) => Ok(token_owner),
+        None => client_db.get_token_owner(token_id.to_string()).await,
+    }
+}
 
-    while let Some(log) = stream.next().await {
-        if let Ok(event) = NFTEvents::decode_raw_log(log.topics(), &log.data().data, true) {
-            let db = db.clone();
-            let twitter_builder = twitter_builder.clone();
-            let client_db = client_db.clone();
-            tokio::spawn(async move {
-                if let Err(e) =
-                    handle_event(db, client_db, twitter_builder, log.transaction_hash, event).await
-                {
-                    log::error!("Error handling event: {:?}", e);
-                }
-            });
+/// Records a redemption whose content was rejected. The local status only changes once the index
+/// has been updated, so that a failure is retried.
+async fn reject_redemption<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    mut redemption: Redemption,
+    token_owner: TokenOwner,
+    content: String,
+    policy: String,
+) -> eyre::Result<()> {
+    let token_id = redemption.token_id.clone();
+    client_db
+        .add_redeemed_tweet(
+            token_owner,
+            token_id.clone(),
+            content,
+            policy,
+            RedemptionStatus::Rejected,
+            TokenType::Tweet,
+            "".to_string(),
+        )
+        .await?;
+    client_db.delete_token(token_id.clone()).await?;
+    log::info!("NFT {} deleted on postgresdb.", token_id);
+
+    redemption.status = RedemptionStatus::Rejected;
+    db.lock().await.set_redemption(redemption)?;
+    Ok(())
+}
+
+/// The type a token was redeemed as. The contract emits RedeemLike for retweets too, so it is read
+/// from the `redeem` call of the transaction that emitted the event, if it called `redeem`
+/// directly.
+async fn get_redeemed_token_type(
+    rpc_url: String,
+    tx_hash: FixedBytes<32>,
+) -> eyre::Result<Option<TokenType>> {
+    let provider = ProviderBuilder::new().on_http(rpc_url.parse()?);
+    let tx = provider
+        .get_transaction_by_hash(tx_hash)
+        .await?
+        .ok_or_eyre("Redeem transaction not found")?;
+    match NFT::redeemCall::abi_decode(&tx.input, true) {
+        Ok(call) => Ok(Some(TokenType::from_u8(call.tokenType)?)),
+        Err(e) => {
+            log::warn!("Redeem tx {} is not a call to redeem: {:?}", tx_hash, e);
+            Ok(None)
         }
     }
+}
 
+async fn handle_redeem_like<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    job_queue: Arc<JobQueue>,
+    rpc_url: String,
+    tx_hash: Option<FixedBytes<32>>,
+    redeem: RedeemLike,
+) -> eyre::Result<()> {
+    let token_id = redeem.tokenId.to_string();
+    let tx_hash = tx_hash.ok_or_eyre("Transaction hash is missing")?;
+    let redeemed_token_type = get_redeemed_token_type(rpc_url, tx_hash).await?;
+
+    let mut db_lock = db.lock().await;
+    let mut redemption = db_lock
+        .get_redemption(token_id.clone())
+        .unwrap_or_else(|_| Redemption::pending(token_id.clone(), None, redeem.tweetId.clone()));
+    let token_type = match redeemed_token_type {
+        Some(token_type) => token_type,
+        // Redeems sent through another contract, e.g. a smart account, keep the type `/redeem`
+        // stored, or else are taken to be likes.
+        None if redemption.token_type != TokenType::Tweet => redemption.token_type,
+        None => TokenType::Like,
+    };
+    redemption.token_type = token_type;
+    db_lock.set_redemption(redemption)?;
+    drop(db_lock);
+
+    job_queue
+        .enqueue(JobKind::ReactToTweet(ReactionJob {
+            token_id,
+            token_type,
+            x_id: redeem.x_id.to_string(),
+            tweet_id: redeem.tweetId,
+            policy: redeem.policy,
+            token_owner: None,
+        }))
+        .await?;
     Ok(())
 }
 
-async fn handle_event<A: TeleportDB>(
+/// Posts the tweet for an approved redemption and records it. Safe to retry: the tweet is only
+/// posted once even if the index updates fail afterwards, provided the tweet id it stores in the
+/// TeleportDB survives restarts (see [`TeleportDB::is_durable`]).
+pub async fn complete_redemption<A: TeleportDB>(
     db: Arc<Mutex<A>>,
     client_db: ClientDB,
     twitter_builder: TwitterBuilder,
-    tx_hash: Option<FixedBytes<32>>,
-    event: NFTEvents,
+    policy_checker: Arc<dyn PolicyChecker>,
+    job: RedemptionJob,
 ) -> eyre::Result<()> {
-    match event {
-        NFTEvents::RedeemTweet(redeem) => {
-            if let Err(e) = handle_redeem_tweet(db, client_db, twitter_builder, redeem).await {
-                log::error!("Error handling RedeemTweet event: {:?}", e);
-            }
+    let db_lock = db.lock().await;
+    let mut redemption = db_lock
+        .get_redemption(job.token_id.clone())
+        .unwrap_or_else(|_| Redemption::pending(job.token_id.clone(), None, job.content.clone()));
+    drop(db_lock);
+    // Already recorded by an earlier run of a replayed event.
+    if matches!(redemption.status, RedemptionStatus::Posted | RedemptionStatus::Rejected) {
+        return Ok(());
+    }
+    let token_owner = resolve_token_owner(&client_db, &job.token_id, job.token_owner).await?;
+
+    let tweet_content = match RedeemContent::parse(&job.content)
+        .and_then(|tweet_content| tweet_content.tweet().map(|_| tweet_content))
+    {
+        Ok(tweet_content) => tweet_content,
+        Err(e) => {
+            log::info!("NFT {} redeem has malformed content: {:?}", job.token_id, e);
+            redemption.error = Some(e.to_string());
+            return reject_redemption(
+                db,
+                client_db,
+                redemption,
+                token_owner,
+                job.content,
+                job.policy,
+            )
+            .await;
         }
-        NFTEvents::NewTokenData(new_token_data) => {
-            if let Err(e) = handle_new_token_data(db, client_db, tx_hash, new_token_data).await {
-                log::error!("Error handling NewTokenData event: {:?}", e);
-            }
+    };
+    // Checked by the first attempt and kept, so that retries post exactly what was checked.
+    let verdict = match redemption.verdict.clone() {
+        Some(verdict) => verdict,
+        None => {
+            let (verdict, media_hashes) = check_redeem_content(
+                &db,
+                &twitter_builder,
+                policy_checker.as_ref(),
+                &job.address,
+                &tweet_content,
+                job.policy.clone(),
+            )
+            .await?;
+            redemption.verdict = Some(verdict.clone());
+            redemption.media_hashes = media_hashes;
+            db.lock().await.set_redemption(redemption.clone())?;
+            verdict
         }
-        NFTEvents::Transfer(transfer) => {
-            if let Err(e) = handle_transfer(client_db, transfer).await {
-                log::error!("Error handling Transfer event: {:?}", e);
+    };
+    if !verdict.is_safe() {
+        log::info!(
+            "NFT {} redeem rejected: {} (violated: {:?})",
+            job.token_id,
+            verdict.rationale,
+            verdict.violated_clause
+        );
+        return reject_redemption(
+            db,
+            client_db,
+            redemption,
+            token_owner,
+            tweet_content.text,
+            job.policy,
+        )
+        .await;
+    }
+
+    if redemption.tweet_id.is_none() {
+        let batch_tweet = match &job.group {
+            Some(group) => db.lock().await.get_batch_tweet(group.clone()).ok(),
+            None => None,
+        };
+        let tweet_id = match batch_tweet {
+            Some(tweet_id) => tweet_id,
+            None => {
+                post_redeemed_tweet(
+                    db.clone(),
+                    twitter_builder,
+                    &job.address,
+                    &tweet_content,
+                    &redemption.media_hashes,
+                )
+                .await?
             }
+        };
+        redemption.tweet_id = Some(tweet_id.clone());
+        let mut db_lock = db.lock().await;
+        if let Some(group) = &job.group {
+            db_lock.add_batch_tweet(group.clone(), tweet_id.clone())?;
         }
-        _ => {}
-    };
-    Ok(())
+        db_lock.add_tweet(job.token_id.clone(), tweet_id)?;
+        db_lock.set_redemption(redemption.clone())?;
+        drop(db_lock);
+    }
+
+    record_posted(db, client_db, redemption, token_owner, tweet_content.text, job.policy).await
 }
 
-async fn handle_redeem_tweet<A: TeleportDB>(
+/// Likes or retweets for a redeemed reaction token and records it. Safe to retry like
+/// [`complete_redemption`].
+pub async fn complete_reaction<A: TeleportDB>(
     db: Arc<Mutex<A>>,
     client_db: ClientDB,
     twitter_builder: TwitterBuilder,
-    redeem: RedeemTweet,
+    job: ReactionJob,
 ) -> eyre::Result<()> {
-    let safe = oai::is_tweet_safe(&redeem.content, &redeem.policy).await;
-    if safe {
-        let db_lock = db.lock().await;
-        let user = db_lock.get_user_by_address(redeem.addr.to_string()).ok();
-        drop(db_lock);
-        let mut tweet_content = TweetContent { text: redeem.content.clone(), media_url: None };
+    let db_lock = db.lock().await;
+    let mut redemption = db_lock
+        .get_redemption(job.token_id.clone())
+        .unwrap_or_else(|_| Redemption::pending(job.token_id.clone(), None, job.tweet_id.clone()));
+    let user = db_lock.get_user_by_x_id(job.x_id.clone())?;
+    drop(db_lock);
+    if redemption.status == RedemptionStatus::Posted {
+        return Ok(());
+    }
+    let token_owner = resolve_token_owner(&client_db, &job.token_id, job.token_owner).await?;
 
-        if let Some(user) = user {
-            let client = twitter_builder
-                .with_auth(user.access_tokens.ok_or_eyre("User has no access tokens")?.into());
+    if redemption.tweet_id.is_none() {
+        let client = user_client(&db, &twitter_builder, user).await?;
+        match job.token_type {
+            TokenType::Retweet => client.retweet(job.x_id.clone(), job.tweet_id.clone()).await?,
+            _ => client.like(job.x_id.clone(), job.tweet_id.clone()).await?,
+        }
+        redemption.tweet_id = Some(job.tweet_id.clone());
+        let mut db_lock = db.lock().await;
+        db_lock.set_redemption(redemption.clone())?;
+        drop(db_lock);
+    }
 
-            // to be backwards compatible for now
-            if let Ok(parsed_tweet_content) = serde_json::from_str::<TweetContent>(&redeem.content)
-            {
-                tweet_content.text = parsed_tweet_content.text;
-                if let Some(media_url) = parsed_tweet_content.media_url {
-                    let media_bytes = reqwest::get(media_url).await?.bytes().await?.to_vec();
-                    let media_id = client.upload_media(media_bytes, None).await?;
-                    tweet_content.media_url = Some(media_id);
-                }
-            }
+    record_posted(db, client_db, redemption, token_owner, job.tweet_id, job.policy).await
+}
 
-            let mut tweet = Tweet::new(tweet_content.text.clone());
-            if let Some(media_id) = tweet_content.media_url {
-                tweet.set_media_ids(vec![media_id]);
-            }
+async fn record_posted<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    mut redemption: Redemption,
+    token_owner: TokenOwner,
+    content: String,
+    policy: String,
+) -> eyre::Result<()> {
+    let token_id = redemption.token_id.clone();
+    // A redemption that was dead-lettered before has already been recorded as failed.
+    if redemption.status == RedemptionStatus::Failed {
+        client_db.set_redemption_status(token_id.clone(), RedemptionStatus::Posted).await?;
+    } else {
+        client_db
+            .add_redeemed_tweet(
+                token_owner.clone(),
+                token_id.clone(),
+                content,
+                policy,
+                RedemptionStatus::Posted,
+                redemption.token_type,
+                redemption.tweet_id.clone().unwrap_or_default(),
+            )
+            .await?;
+    }
+    client_db.increment_user_redeemed(token_owner.user_id).await?;
+    client_db.delete_token(token_id.clone()).await?;
+    log::info!("NFT {} deleted on postgresdb.", token_id);
+
+    redemption.status = RedemptionStatus::Posted;
+    redemption.error = None;
+    let mut db_lock = db.lock().await;
+    db_lock.set_redemption(redemption)?;
+    drop(db_lock);
+    Ok(())
+}
 
-            let tweet_id = client.raw_tweet(tweet).await?;
+/// Records a redemption whose side effects ran out of retries. The redemption is marked failed
+/// even if the index cannot be updated.
+pub async fn fail_redemption<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    job: RedemptionJob,
+    error: String,
+) -> eyre::Result<()> {
+    let text = RedeemContent::parse(&job.content).map_or(job.content.clone(), |c| c.text);
+    let fallback = Redemption::pending(job.token_id, None, job.content);
+    record_failed(db, client_db, fallback, job.token_owner, text, job.policy, error).await
+}
 
-            let mut db = db.lock().await;
-            db.add_tweet(redeem.tokenId.to_string(), tweet_id)?;
-            drop(db);
-        }
+/// Records a reaction whose side effects ran out of retries.
+pub async fn fail_reaction<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    job: ReactionJob,
+    error: String,
+) -> eyre::Result<()> {
+    let fallback = Redemption {
+        token_type: job.token_type,
+        ..Redemption::pending(job.token_id, None, job.tweet_id.clone())
+    };
+    record_failed(db, client_db, fallback, job.token_owner, job.tweet_id, job.policy, error).await
+}
 
-        let token_id = redeem.tokenId.to_string();
-        let token_owner = client_db.get_token_owner(token_id.clone()).await?;
+async fn record_failed<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    fallback: Redemption,
+    token_owner: Option<TokenOwner>,
+    content: String,
+    policy: String,
+    error: String,
+) -> eyre::Result<()> {
+    let token_id = fallback.token_id.clone();
+    let mut db_lock = db.lock().await;
+    let mut redemption = db_lock.get_redemption(token_id.clone()).unwrap_or(fallback);
+    let already_failed = redemption.status == RedemptionStatus::Failed;
+    let token_type = redemption.token_type;
+    redemption.status = RedemptionStatus::Failed;
+    redemption.error = Some(error);
+    db_lock.set_redemption(redemption)?;
+    drop(db_lock);
+
+    if !already_failed {
+        let token_owner = resolve_token_owner(&client_db, &token_id, token_owner).await?;
         client_db
             .add_redeemed_tweet(
-                token_owner.clone(),
+                token_owner,
                 token_id.clone(),
-                tweet_content.text,
-                redeem.policy,
+                content,
+                policy,
+                RedemptionStatus::Failed,
+                token_type,
+                "".to_string(),
             )
             .await?;
-        client_db.increment_user_redeemed(token_owner.user_id).await?;
         client_db.delete_token(token_id).await?;
-        log::info!("NFT {} deleted on postgresdb.", redeem.tokenId.to_string());
     }
     Ok(())
 }
 
+/// Downloads the tweet's media and checks that it can be attached to one tweet. Returns the media
+/// with the sha256 of each file.
+pub async fn fetch_media(
+    tweet_content: &RedeemContent,
+) -> eyre::Result<(Vec<PolicyMedia>, Vec<String>)> {
+    let media_urls = &tweet_content.media_urls;
+    if media_urls.len() > MAX_IMAGES {
+        eyre::bail!("A tweet can have at most {} media", MAX_IMAGES);
+    }
+    let config = FetchConfig::from_env();
+    let mut media = vec![];
+    let mut media_types = vec![];
+    let mut hashes = vec![];
+    for media_url in media_urls {
+        let fetched = media_fetch::fetch_media(&config, media_url).await?;
+        let media_type = sniff_media_type(&fetched.bytes)?;
+        let (family, _) = media_type.mime_type.split_once('/').unwrap_or_default();
+        if !fetched.content_type.starts_with(family) {
+            eyre::bail!(
+                "Media served as {} but contains {}",
+                fetched.content_type,
+                media_type.mime_type
+            );
+        }
+        media_types.push((media_type, fetched.bytes.len()));
+        media.push(PolicyMedia {
+            mime_type: media_type.mime_type.to_string(),
+            bytes: fetched.bytes,
+        });
+        hashes.push(fetched.sha256);
+    }
+    validate_media(&media_types)?;
+    Ok((media, hashes))
+}
+
+async fn post_redeemed_tweet<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    twitter_builder: TwitterBuilder,
+    address: &str,
+    tweet_content: &RedeemContent,
+    checked_media_hashes: &[String],
+) -> eyre::Result<String> {
+    let db_lock = db.lock().await;
+    let user = db_lock.get_user_by_address(address.to_string())?;
+    drop(db_lock);
+
+    let access_tokens = user.access_tokens.clone();
+    let client = user_client(&db, &twitter_builder, user).await?;
+    // Media can only be uploaded with OAuth 1.0a, so use those tokens if the user has them.
+    let oauth1_client = match access_tokens {
+        Some(access_tokens) if client.client.is_oauth2() => {
+            Some(twitter_builder.with_auth(access_tokens.into()))
+        }
+        _ => None,
+    };
+    let upload_client = oauth1_client.as_ref().unwrap_or(&client);
+
+    let mut tweet = tweet_content.tweet()?;
+    let (media, media_hashes) = fetch_media(tweet_content).await?;
+    // The media must be exactly what the policy checker saw.
+    if media_hashes != checked_media_hashes {
+        eyre::bail!("Media changed since it was checked against the policy");
+    }
+    if !media.is_empty() {
+        let mut media_ids = vec![];
+        for media in media {
+            media_ids.push(upload_client.upload_media_file(media.bytes).await?);
+        }
+        tweet.set_media_ids(media_ids);
+    }
+
+    client.raw_tweet(tweet).await
+}
+
 async fn handle_new_token_data<A: TeleportDB>(
     db: Arc<Mutex<A>>,
     client_db: ClientDB,
@@ -216,10 +664,12 @@ async fn handle_transfer(client_db: ClientDB, transfer: Transfer) -> eyre::Resul
     let to = transfer.to.to_string();
     let token_id = transfer.tokenId.to_string();
 
-    if from == "0x0000000000000000000000000000000000000000" {
+    // A burn comes from a redeem, whose job still looks up the token's creator in the index and
+    // deletes the token once the redemption is recorded.
+    if from == "0x0000000000000000000000000000000000000000" ||
+        to == "0x0000000000000000000000000000000000000000"
+    {
         // Do nothing
-    } else if to == "0x0000000000000000000000000000000000000000" {
-        client_db.delete_token(token_id.clone()).await?;
     } else {
         client_db.update_token_owner(token_id.clone(), to.clone()).await?;
     }
@@ -228,8 +678,88 @@ async fn handle_transfer(client_db: ClientDB, transfer: Transfer) -> eyre::Resul
     Ok(())
 }
 
+/// Undoes the index updates of an event whose log was removed by a chain reorganization.
+pub async fn rollback_event<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    client_db: ClientDB,
+    job_queue: Arc<JobQueue>,
+    tx_hash: Option<FixedBytes<32>>,
+    event: NFTEvents,
+) -> eyre::Result<()> {
+    match event {
+        NFTEvents::NewTokenData(new_token_data) => {
+            let token_id = new_token_data.tokenId.to_string();
+            // Back to pending, so that the mint is promoted again if it is included again.
+            let tx_hash = tx_hash.ok_or_eyre("Transaction hash is missing")?;
+            if let Err(e) =
+                db.lock().await.demote_nft(token_id.clone(), tx_hash.encode_hex_with_prefix())
+            {
+                log::warn!("NFT {} could not be demoted: {:?}", token_id, e);
+            }
+            client_db.delete_token(token_id.clone()).await?;
+            log::info!("NFT {} mint rolled back.", token_id);
+        }
+        NFTEvents::Transfer(transfer) => {
+            let token_id = transfer.tokenId.to_string();
+            if transfer.from == Address::ZERO || transfer.to == Address::ZERO {
+                // Rolled back together with the NewTokenData event of the mint, or the redeem
+                // event of the burn.
+            } else {
+                client_db.update_token_owner(token_id.clone(), transfer.from.to_string()).await?;
+                log::info!("NFT {} transfer to {} rolled back.", token_id, transfer.to);
+            }
+        }
+        NFTEvents::RedeemTweet(RedeemTweet { tokenId, .. }) |
+        NFTEvents::RedeemLike(RedeemLike { tokenId, .. }) => {
+            let token_id = tokenId.to_string();
+            match job_queue.cancel_redemption(&token_id).await? {
+                CancelOutcome::Cancelled => {}
+                CancelOutcome::Running => {
+                    log::error!("NFT {} redeem was reorged out while its job runs.", token_id);
+                    return Ok(());
+                }
+                CancelOutcome::AlreadyDone => {
+                    log::warn!("NFT {} redeem was reorged out after its job failed.", token_id);
+                    return Ok(());
+                }
+                CancelOutcome::NotFound => {
+                    let status = db
+                        .lock()
+                        .await
+                        .get_redemption(token_id.clone())
+                        .ok()
+                        .map(|redemption| redemption.status);
+                    if status == Some(RedemptionStatus::Posted) {
+                        log::error!(
+                            "NFT {} redeem was reorged out after its tweet was posted.",
+                            token_id
+                        );
+                    } else {
+                        log::warn!(
+                            "NFT {} redeem was reorged out with no job queued ({:?}).",
+                            token_id,
+                            status
+                        );
+                    }
+                    return Ok(());
+                }
+            }
+            let mut db_lock = db.lock().await;
+            if let Ok(mut redemption) = db_lock.get_redemption(token_id.clone()) {
+                redemption.status = RedemptionStatus::Pending;
+                redemption.verdict = None;
+                redemption.error = Some("Redeem was removed by a chain reorganization".to_string());
+                db_lock.set_redemption(redemption)?;
+            }
+            log::info!("NFT {} redeem rolled back.", token_id);
+        }
+        _ => {}
+    };
+    Ok(())
+}
+
 pub async fn mint_nft(
-    provider: WalletProvider,
+    tx_manager: &Arc<TxManager>,
     recipient: Address,
     x_id: String,
     policy: String,
@@ -237,77 +767,134 @@ pub async fn mint_nft(
     username: String,
     pfp_url: String,
     nft_id: String,
-) -> eyre::Result<String> {
+) -> eyre::Result<PendingTx> {
     let nft_address = get_nft_address()?;
-    let nft = NFT::new(nft_address, provider);
+    let nft = NFT::new(nft_address, tx_manager.provider());
     let nftid_hash = keccak256(nft_id.as_bytes());
     let mint =
         nft.mintTo(recipient, Uint::from_str(&x_id)?, policy, name, username, pfp_url, nftid_hash);
-    let tx = mint.send().await?;
-
-    let tx_hash = tx.tx_hash();
+    let pending = tx_manager.send(mint.into_transaction_request()).await?;
 
-    log::info!("Minted NFT with tx hash: {}", tx_hash);
+    log::info!("Minted NFT with tx hash: {}", pending.tx_hash);
 
-    Ok(tx_hash.encode_hex_with_prefix())
+    Ok(pending)
 }
 
 pub async fn redeem_nft(
-    provider: WalletProvider,
+    tx_manager: &Arc<TxManager>,
     token_id: String,
     content: String,
-) -> eyre::Result<String> {
+    token_type: TokenType,
+) -> eyre::Result<PendingTx> {
     let nft_address = get_nft_address()?;
-    let nft = NFT::new(nft_address, provider);
+    let nft = NFT::new(nft_address, tx_manager.provider());
     let token_id = Uint::from_str(&token_id)?;
-    let redeem = nft.redeem(token_id, content, 0u8);
-    let tx = redeem.send().await?;
+    let redeem = nft.redeem(token_id, content, token_type.as_u8());
+    let pending = tx_manager.send(redeem.into_transaction_request()).await?;
 
-    let tx_hash = tx.tx_hash();
+    log::info!("Redeemed NFT with tx hash: {}", pending.tx_hash);
+    Ok(pending)
+}
 
-    log::info!("Redeemed NFT with tx hash: {}", tx_hash);
-    Ok(tx_hash.encode_hex_with_prefix())
+pub async fn redeem_batch_nft(
+    tx_manager: &Arc<TxManager>,
+    token_ids: Vec<String>,
+    content: String,
+) -> eyre::Result<PendingTx> {
+    let batch_redeem = Redeem::new(get_batch_redeem_address()?, tx_manager.provider());
+    let token_ids =
+        token_ids.iter().map(|token_id| Uint::from_str(token_id)).collect::<Result<Vec<_>, _>>()?;
+    let redeem = batch_redeem.redeem(token_ids, content);
+    let pending = tx_manager.send(redeem.into_transaction_request()).await?;
+
+    log::info!("Batch redeemed NFTs with tx hash: {}", pending.tx_hash);
+    Ok(pending)
 }
 
 #[derive(Debug, Serialize)]
-pub enum NFTAction {
-    Redeem {
-        token_id: String,
-        content: String,
-    },
-    Mint {
-        recipient: Address,
-        policy: String,
-        x_id: String,
-        name: String,
-        username: String,
-        pfp_url: String,
-        nft_id: String,
-    },
+pub struct MintedNFT {
+    pub tx_hash: String,
+    pub token_id: String,
+    pub nft_id: String,
 }
 
-pub async fn nft_action_consumer(
-    mut receiver: mpsc::Receiver<(NFTAction, oneshot::Sender<String>)>,
-    provider: WalletProvider,
-) {
-    while let Some(action) = receiver.recv().await {
-        let (action, sender) = action;
-        let tx_hash = match action {
-            NFTAction::Redeem { token_id, content } => {
-                redeem_nft(provider.clone(), token_id, content).await
-            }
-            NFTAction::Mint { recipient, policy, x_id, name, username, pfp_url, nft_id } => {
-                mint_nft(provider.clone(), recipient, x_id, policy, name, username, pfp_url, nft_id)
-                    .await
+/// Waits for a mint to be mined and reports its token id. The pending NFT is only promoted once
+/// the `NewTokenData` event is confirmed, since the receipt's block may still be reorged out.
+pub async fn wait_for_mint<A: TeleportDB>(
+    db: &Mutex<A>,
+    pending: PendingTx,
+    nft_id: String,
+) -> eyre::Result<MintedNFT> {
+    let pending_tx_hash = pending.tx_hash.clone();
+    let receipt = pending.receipt().await?;
+    let new_token_data = receipt
+        .inner
+        .logs()
+        .iter()
+        .filter_map(|log| NFTEvents::decode_raw_log(log.topics(), &log.data().data, true).ok())
+        .find_map(|event| match event {
+            NFTEvents::NewTokenData(new_token_data) => Some(new_token_data),
+            _ => None,
+        })
+        .ok_or_eyre("Mint receipt has no NewTokenData event")?;
+    let token_id = new_token_data.tokenId.to_string();
+    let mined_hash = receipt.transaction_hash.encode_hex_with_prefix();
+    if mined_hash != pending_tx_hash {
+        db.lock().await.rename_pending_nft(pending_tx_hash, mined_hash.clone())?;
+    }
+
+    Ok(MintedNFT { tx_hash: mined_hash, token_id, nft_id })
+}
+
+/// Waits for a mint to be mined and re-keys its pending NFT if the transaction was replaced, so
+/// that the `NewTokenData` event finds it.
+pub async fn track_mint<A: TeleportDB>(db: Arc<Mutex<A>>, pending: PendingTx) {
+    let tx_hash = pending.tx_hash.clone();
+    match pending.receipt().await {
+        Ok(receipt) => {
+            let mined_hash = receipt.transaction_hash.encode_hex_with_prefix();
+            if mined_hash != tx_hash {
+                if let Err(e) = db.lock().await.rename_pending_nft(tx_hash, mined_hash) {
+                    log::error!("Failed to re-key replaced mint: {:?}", e);
+                }
             }
-        };
+        }
+        Err(e) => log::error!("Mint tx {} failed: {}", tx_hash, e),
+    }
+}
 
-        if let Ok(tx_hash) = tx_hash {
-            if let Err(e) = sender.send(tx_hash) {
-                log::error!("Failed to send tx hash to sender: {:?}", e);
+/// Waits for a redeem to be mined, recording the mined hash or the failure on the redemptions of
+/// every token it redeems.
+pub async fn track_redeem<A: TeleportDB>(
+    db: Arc<Mutex<A>>,
+    token_ids: Vec<String>,
+    pending: PendingTx,
+) {
+    let tx_hash = pending.tx_hash.clone();
+    let result = pending.receipt().await;
+    if let Err(e) = &result {
+        log::error!("Redeem tx {} for NFTs {:?} failed: {}", tx_hash, token_ids, e);
+    }
+    let mut db = db.lock().await;
+    for token_id in token_ids {
+        let Ok(mut redemption) = db.get_redemption(token_id.clone()) else {
+            continue;
+        };
+        match &result {
+            Ok(receipt) => {
+                let mined_hash = receipt.transaction_hash.encode_hex_with_prefix();
+                if mined_hash == tx_hash {
+                    continue;
+                }
+                redemption.tx_hash = Some(mined_hash);
+            }
+            Err(e) => {
+                redemption.status = RedemptionStatus::Failed;
+                redemption.error = Some(e.to_string());
             }
-        } else {
-            log::error!("Failed to send tx hash to sender");
+        }
+        if let Err(e) = db.set_redemption(redemption) {
+            log::error!("Failed to update redemption {}: {:?}", token_id, e);
         }
     }
 }
@@ -332,7 +919,7 @@ mod tests {
         signers::local::{coins_bip39::English, MnemonicBuilder},
     };
 
-    use crate::actions::wallet::get_provider;
+    use crate::actions::{tx_manager::TxConfig, wallet::get_provider};
 
     use super::*;
     #[tokio::test]
@@ -350,10 +937,11 @@ mod tests {
             .unwrap()
             .build()
             .unwrap();
-        let wallet = EthereumWallet::from(signer);
-        let provider = get_provider(rpc_url, wallet);
-        mint_nft(
-            provider,
+        let address = signer.address();
+        let provider = get_provider(rpc_url, EthereumWallet::from(signer));
+        let tx_manager = Arc::new(TxManager::new(provider, address, TxConfig::default()));
+        let pending = mint_nft(
+            &tx_manager,
             recipient_address,
             1.to_string(),
             "policy".to_string(),
@@ -364,5 +952,35 @@ mod tests {
         )
         .await
         .unwrap();
+        pending.receipt().await.unwrap();
+    }
+
+    #[test]
+    fn token_type_abi_test() -> eyre::Result<()> {
+        // `redeem` takes the contract's `enum TokenType { Tweet, Like, Retweet }` as a uint8.
+        let abi: serde_json::Value = serde_json::from_str(include_str!("../../abi/nft.json"))?;
+        let redeem = abi["abi"]
+            .as_array()
+            .ok_or_eyre("ABI is not an array")?
+            .iter()
+            .find(|item| item["type"] == "function" && item["name"] == "redeem")
+            .ok_or_eyre("redeem not found")?;
+        assert_eq!(redeem["inputs"][2]["type"], "uint8");
+        assert_eq!(redeem["inputs"][2]["internalType"], "enum NFT.TokenType");
+
+        for (token_type, value) in
+            [(TokenType::Tweet, 0), (TokenType::Like, 1), (TokenType::Retweet, 2)]
+        {
+            assert_eq!(token_type.as_u8(), value);
+            assert_eq!(TokenType::from_u8(value)?, token_type);
+            let call = NFT::redeemCall {
+                tokenId: Uint::from(1),
+                content: "".to_string(),
+                tokenType: token_type.as_u8(),
+            };
+            assert_eq!(NFT::redeemCall::abi_decode(&call.abi_encode(), true)?.tokenType, value);
+        }
+        assert!(TokenType::from_u8(3).is_err());
+        Ok(())
     }
 }
@@ -0,0 +1,367 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use alloy::{
+    hex::ToHexExt,
+    primitives::B256,
+    providers::{Provider, ProviderBuilder, WsConnect},
+    rpc::types::{BlockNumberOrTag, Filter, Log},
+    sol_types::SolEventInterface,
+    transports::Transport,
+};
+use eyre::OptionExt;
+use futures_util::stream::StreamExt;
+use rand::Rng;
+use serde::Serialize;
+use tokio::{
+    sync::Mutex,
+    time::{sleep, sleep_until, Duration, Instant},
+};
+
+use super::{
+    checkpoint::{Checkpoint, LogKey},
+    jobs::JobQueue,
+    nft::{get_nft_address, handle_event, rollback_event, NFT::NFTEvents},
+};
+use crate::db::{client_db::ClientDB, TeleportDB};
+
+const DEFAULT_BACKFILL_RANGE: u64 = 2000;
+const DEFAULT_CONFIRMATIONS: u64 = 5;
+const DEFAULT_STALL_SECS: u64 = 60;
+const RECONNECT_BASE_DELAY_MS: u64 = 1000;
+const RECONNECT_MAX_DELAY_MS: u64 = 60_000;
+
+/// When a log is considered final enough to act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// Once this many blocks have been built on top of the log's block.
+    Depth(u64),
+    /// Once the log's block is at or below the `finalized` block tag.
+    Finalized,
+}
+
+impl Confirmation {
+    /// Reads `CONFIRMATIONS`, which is either a block count or `finalized`.
+    pub fn from_env() -> eyre::Result<Self> {
+        match std::env::var("CONFIRMATIONS") {
+            Ok(confirmations) if confirmations == "finalized" => Ok(Self::Finalized),
+            Ok(confirmations) => Ok(Self::Depth(confirmations.parse()?)),
+            Err(_) => Ok(Self::Depth(DEFAULT_CONFIRMATIONS)),
+        }
+    }
+
+    /// The highest block whose logs are safe to act on, given the current head.
+    async fn confirmed_block<P, T>(&self, provider: &P, head: u64) -> eyre::Result<u64>
+    where
+        P: Provider<T>,
+        T: Transport + Clone,
+    {
+        match self {
+            Self::Depth(depth) => Ok(head.saturating_sub(*depth)),
+            Self::Finalized => {
+                let block = provider
+                    .get_block_by_number(BlockNumberOrTag::Finalized, false)
+                    .await?
+                    .ok_or_eyre("Finalized block not found")?;
+                Ok(block.header.number)
+            }
+        }
+    }
+}
+
+/// Orders buffered logs by block number and log index. The block and transaction hashes keep
+/// apart logs at the same position on different forks.
+type PendingKey = (u64, u64, B256, B256);
+
+/// Everything needed to act on NFT contract logs.
+pub struct EventHandler<A: TeleportDB> {
+    pub db: Arc<Mutex<A>>,
+    pub client_db: ClientDB,
+    pub job_queue: Arc<JobQueue>,
+    pub rpc_url: String,
+    pub checkpoint: Arc<Checkpoint>,
+    pub confirmation: Confirmation,
+    /// Logs waiting for confirmation.
+    pending: Arc<Mutex<BTreeMap<PendingKey, Log>>>,
+}
+
+impl<A: TeleportDB> Clone for EventHandler<A> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            client_db: self.client_db.clone(),
+            job_queue: self.job_queue.clone(),
+            rpc_url: self.rpc_url.clone(),
+            checkpoint: self.checkpoint.clone(),
+            confirmation: self.confirmation,
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<A: TeleportDB> EventHandler<A> {
+    pub fn new(
+        db: Arc<Mutex<A>>,
+        client_db: ClientDB,
+        job_queue: Arc<JobQueue>,
+        rpc_url: String,
+        checkpoint: Arc<Checkpoint>,
+        confirmation: Confirmation,
+    ) -> Self {
+        Self {
+            db,
+            client_db,
+            job_queue,
+            rpc_url,
+            checkpoint,
+            confirmation,
+            pending: Default::default(),
+        }
+    }
+
+    /// Buffers a log until it is confirmed. A log removed by a reorg is dropped from the buffer,
+    /// or rolled back if it was already processed.
+    pub async fn on_log(&self, log: Log) -> eyre::Result<()> {
+        let (Some(tx_hash), Some(log_index), Some(block_number), Some(block_hash)) =
+            (log.transaction_hash, log.log_index, log.block_number, log.block_hash)
+        else {
+            eyre::bail!("Log is not included in a block yet");
+        };
+        let pending_key = (block_number, log_index, block_hash, tx_hash);
+
+        if !log.removed {
+            self.pending.lock().await.insert(pending_key, log);
+            return Ok(());
+        }
+
+        if self.pending.lock().await.remove(&pending_key).is_some() {
+            log::info!("Dropped unconfirmed log {}:{} removed by a reorg", tx_hash, log_index);
+            return Ok(());
+        }
+        let key = LogKey { tx_hash: tx_hash.encode_hex_with_prefix(), log_index };
+        if self.checkpoint.unmark_seen(&key).await? {
+            log::warn!("Rolling back processed log {:?} removed by a reorg", key);
+            let event = NFTEvents::decode_raw_log(log.topics(), &log.data().data, true)?;
+            rollback_event(
+                self.db.clone(),
+                self.client_db.clone(),
+                self.job_queue.clone(),
+                Some(tx_hash),
+                event,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Processes the buffered logs up to and including `confirmed_block`, in chain order. If a log
+    /// fails, it and the logs after it stay buffered and the checkpoint does not advance, so they
+    /// are retried with the next block or replayed by the backfill after a reconnect.
+    pub async fn release_confirmed(&self, confirmed_block: u64) -> eyre::Result<()> {
+        let mut pending = self.pending.lock().await;
+        let unconfirmed = pending.split_off(&(confirmed_block + 1, 0, B256::ZERO, B256::ZERO));
+        let confirmed = std::mem::replace(&mut *pending, unconfirmed);
+        drop(pending);
+        let mut confirmed = confirmed.into_iter();
+        while let Some((key, log)) = confirmed.next() {
+            if let Err(e) = self.process_log(log.clone()).await {
+                let mut pending = self.pending.lock().await;
+                pending.insert(key, log);
+                pending.extend(confirmed);
+                return Err(e);
+            }
+        }
+        self.checkpoint.advance(confirmed_block).await
+    }
+
+    /// Runs a log's event handler unless it has been processed before, so that replays from
+    /// backfill or resubscription are idempotent. The log only counts as processed once its
+    /// handler has succeeded.
+    pub async fn process_log(&self, log: Log) -> eyre::Result<()> {
+        let (Some(tx_hash), Some(log_index), Some(block_number)) =
+            (log.transaction_hash, log.log_index, log.block_number)
+        else {
+            eyre::bail!("Log is not included in a block yet");
+        };
+        let key = LogKey { tx_hash: tx_hash.encode_hex_with_prefix(), log_index };
+        if self.checkpoint.is_seen(&key).await {
+            log::info!("Skipping already processed log {:?}", key);
+            return Ok(());
+        }
+
+        if let Ok(event) = NFTEvents::decode_raw_log(log.topics(), &log.data().data, true) {
+            handle_event(
+                self.db.clone(),
+                self.client_db.clone(),
+                self.job_queue.clone(),
+                self.rpc_url.clone(),
+                Some(tx_hash),
+                event,
+            )
+            .await?;
+        }
+        self.checkpoint.mark_seen(key, block_number).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    Backfilling,
+    Live,
+    Reconnecting,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SubscriberStatus {
+    pub state: ConnectionState,
+    /// Latest head seen on the live subscription.
+    pub last_block: Option<u64>,
+    /// Unix timestamp (seconds) of the latest head.
+    pub last_head_at: Option<i64>,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+}
+
+pub type SubscriberStatusHandle = Arc<Mutex<SubscriberStatus>>;
+
+/// Keeps the NFT event subscription alive: every time the websocket drops, errors or stalls, it
+/// reconnects with jittered exponential backoff and backfills from the last checkpoint.
+pub async fn run_event_subscriber<A: TeleportDB>(
+    handler: EventHandler<A>,
+    ws_rpc_url: String,
+    status: SubscriberStatusHandle,
+) {
+    let stall_timeout = Duration::from_secs(
+        std::env::var("SUBSCRIBER_STALL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(DEFAULT_STALL_SECS),
+    );
+    let mut failures = 0;
+    loop {
+        let error =
+            match subscribe_to_nft_events(&handler, &ws_rpc_url, &status, stall_timeout).await {
+                Ok(()) => "Subscription ended".to_string(),
+                Err(e) => e.to_string(),
+            };
+
+        let mut status = status.lock().await;
+        // Only back off further if we never made it to a live subscription.
+        if status.state == ConnectionState::Live {
+            failures = 0;
+        }
+        failures += 1;
+        let delay = reconnect_delay(failures);
+        log::warn!("NFT event subscriber disconnected: {}. Reconnecting in {:?}", error, delay);
+        status.state = ConnectionState::Reconnecting;
+        status.reconnects += 1;
+        status.last_error = Some(error);
+        drop(status);
+        sleep(delay).await;
+    }
+}
+
+fn reconnect_delay(failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(6);
+    let cap = (RECONNECT_BASE_DELAY_MS << exponent).min(RECONNECT_MAX_DELAY_MS);
+    Duration::from_millis(cap / 2 + rand::thread_rng().gen_range(0..=cap / 2))
+}
+
+async fn set_state(status: &SubscriberStatusHandle, state: ConnectionState) {
+    status.lock().await.state = state;
+}
+
+async fn subscribe_to_nft_events<A: TeleportDB>(
+    handler: &EventHandler<A>,
+    ws_rpc_url: &str,
+    status: &SubscriberStatusHandle,
+    stall_timeout: Duration,
+) -> eyre::Result<()> {
+    set_state(status, ConnectionState::Connecting).await;
+    let ws = WsConnect::new(ws_rpc_url);
+    let provider = ProviderBuilder::new().on_ws(ws).await?;
+    let nft_address = get_nft_address()?;
+    let filter = Filter::new().address(nft_address);
+
+    // Subscribe before backfilling so no log falls in between; the overlap is deduplicated.
+    let sub = provider.subscribe_logs(&filter.clone().from_block(BlockNumberOrTag::Latest)).await?;
+    let mut logs = sub.into_stream();
+    let mut heads = provider.subscribe_blocks().await?.into_stream();
+
+    log::info!("Subscribed to events for contract at: {}", nft_address.to_string());
+
+    set_state(status, ConnectionState::Backfilling).await;
+    backfill(&provider, handler, &filter).await?;
+    set_state(status, ConnectionState::Live).await;
+
+    let mut last_head_at = Instant::now();
+    loop {
+        tokio::select! {
+            log = logs.next() => {
+                let log = log.ok_or_eyre("Log subscription ended")?;
+                if let Err(e) = handler.on_log(log).await {
+                    log::error!("Error processing log: {:?}", e);
+                }
+            }
+            head = heads.next() => {
+                head.ok_or_eyre("Block subscription ended")?;
+                last_head_at = Instant::now();
+                let block_number = provider.get_block_number().await?;
+                let confirmed_block =
+                    handler.confirmation.confirmed_block(&provider, block_number).await?;
+                handler.release_confirmed(confirmed_block).await?;
+                let mut status = status.lock().await;
+                status.last_block = Some(block_number);
+                status.last_head_at = Some(chrono::Utc::now().timestamp());
+            }
+            _ = sleep_until(last_head_at + stall_timeout) => {
+                eyre::bail!("No new heads for {:?}", stall_timeout);
+            }
+        }
+    }
+}
+
+/// Replays logs from the last checkpoint (or `NFT_START_BLOCK` on first start) up to the current
+/// head with `eth_getLogs`, in ranges of `BACKFILL_RANGE` blocks. Logs that are not confirmed yet
+/// stay buffered until the live subscription confirms them.
+async fn backfill<A, P, T>(
+    provider: &P,
+    handler: &EventHandler<A>,
+    filter: &Filter,
+) -> eyre::Result<()>
+where
+    A: TeleportDB,
+    P: Provider<T>,
+    T: Transport + Clone,
+{
+    let start_block = match handler.checkpoint.last_block().await {
+        Some(block) => Some(block),
+        None => std::env::var("NFT_START_BLOCK").ok().map(|block| block.parse()).transpose()?,
+    };
+    let Some(mut from) = start_block else {
+        log::info!("No checkpoint found, skipping backfill");
+        return Ok(());
+    };
+    let range = std::env::var("BACKFILL_RANGE")
+        .ok()
+        .and_then(|range| range.parse().ok())
+        .unwrap_or(DEFAULT_BACKFILL_RANGE)
+        .max(1);
+    let head = provider.get_block_number().await?;
+    let confirmed_block = handler.confirmation.confirmed_block(provider, head).await?;
+
+    while from <= head {
+        let to = (from + range - 1).min(head);
+        let logs = provider.get_logs(&filter.clone().from_block(from).to_block(to)).await?;
+        log::info!("Backfilling {} logs from blocks {}..={}", logs.len(), from, to);
+        for log in logs {
+            handler.on_log(log).await?;
+        }
+        handler.release_confirmed(to.min(confirmed_block)).await?;
+        from = to + 1;
+    }
+    Ok(())
+}
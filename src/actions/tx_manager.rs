@@ -0,0 +1,357 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use alloy::{
+    hex::ToHexExt,
+    network::TransactionBuilder,
+    primitives::{Address, TxHash},
+    providers::{utils::Eip1559Estimation, Provider},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+    transports::TransportError,
+};
+use tokio::{
+    sync::{oneshot, Mutex},
+    time::{sleep, Instant},
+};
+
+use super::wallet::WalletProvider;
+
+/// A failed transaction, classified by what the caller should do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxError {
+    /// The node could not be reached; worth retrying.
+    Rpc(String),
+    /// The node disagreed with the local nonce. The nonce is re-read from the node before the
+    /// next transaction.
+    NonceConflict(String),
+    /// The minter wallet cannot pay for gas.
+    InsufficientFunds,
+    /// Gas estimation reverted, so the transaction was never broadcast.
+    WouldRevert(String),
+    /// The node refused the transaction for another reason.
+    Rejected(String),
+    /// The transaction was mined but reverted.
+    Reverted { tx_hash: String },
+    /// The transaction was not mined after every gas bump. It may still be mined, so it must not
+    /// be sent again.
+    Timeout { tx_hash: String },
+}
+
+impl TxError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, TxError::Rpc(_) | TxError::NonceConflict(_))
+    }
+
+    /// Classifies a JSON-RPC error returned by the node for `eth_sendRawTransaction` or
+    /// `eth_estimateGas`.
+    fn from_rejection(message: &str) -> Self {
+        let lower = message.to_lowercase();
+        if lower.contains("nonce too low") ||
+            lower.contains("nonce too high") ||
+            lower.contains("already known") ||
+            lower.contains("replacement transaction underpriced")
+        {
+            TxError::NonceConflict(message.to_string())
+        } else if lower.contains("insufficient funds") {
+            TxError::InsufficientFunds
+        } else if lower.contains("revert") {
+            TxError::WouldRevert(message.to_string())
+        } else {
+            TxError::Rejected(message.to_string())
+        }
+    }
+}
+
+impl From<TransportError> for TxError {
+    fn from(error: TransportError) -> Self {
+        match error.as_error_resp() {
+            Some(payload) => TxError::from_rejection(&payload.message),
+            None => TxError::Rpc(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::Rpc(message) => write!(f, "RPC error: {}", message),
+            TxError::NonceConflict(message) => write!(f, "Nonce conflict: {}", message),
+            TxError::InsufficientFunds => write!(f, "Minter wallet has insufficient funds"),
+            TxError::WouldRevert(message) => write!(f, "Transaction would revert: {}", message),
+            TxError::Rejected(message) => write!(f, "Transaction rejected: {}", message),
+            TxError::Reverted { tx_hash } => write!(f, "Transaction {} reverted", tx_hash),
+            TxError::Timeout { tx_hash } => write!(f, "Transaction {} was not mined", tx_hash),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+#[derive(Debug, Clone)]
+pub struct TxConfig {
+    pub poll_interval: Duration,
+    /// How long a transaction may sit in the mempool before it is replaced with higher fees.
+    pub stuck_after: Duration,
+    /// Nodes only accept a replacement that pays at least 10% more than the one it replaces.
+    pub gas_bump_percent: u128,
+    pub max_bumps: u32,
+}
+
+impl Default for TxConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            stuck_after: Duration::from_secs(60),
+            gas_bump_percent: 20,
+            max_bumps: 5,
+        }
+    }
+}
+
+impl TxConfig {
+    /// Reads `TX_POLL_INTERVAL_MS`, `TX_STUCK_AFTER_SECS`, `TX_GAS_BUMP_PERCENT` and
+    /// `TX_MAX_BUMPS`, falling back to the defaults for any that are unset.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        Self {
+            poll_interval: var("TX_POLL_INTERVAL_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.poll_interval),
+            stuck_after: var("TX_STUCK_AFTER_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.stuck_after),
+            gas_bump_percent: var("TX_GAS_BUMP_PERCENT")
+                .map(|percent| percent.max(10) as u128)
+                .unwrap_or(default.gas_bump_percent),
+            max_bumps: var("TX_MAX_BUMPS").map(|v| v as u32).unwrap_or(default.max_bumps),
+        }
+    }
+}
+
+/// A broadcast transaction. It is watched, and replaced if stuck, whether or not anyone waits
+/// for the receipt.
+pub struct PendingTx {
+    /// Hash of the first broadcast. The mined transaction has a different hash if it was
+    /// replaced.
+    pub tx_hash: String,
+    receipt: oneshot::Receiver<Result<TransactionReceipt, TxError>>,
+}
+
+impl PendingTx {
+    pub async fn receipt(self) -> Result<TransactionReceipt, TxError> {
+        self.receipt
+            .await
+            .unwrap_or_else(|_| Err(TxError::Rpc("Transaction watcher stopped".to_string())))
+    }
+}
+
+/// Sends the minter's transactions with locally tracked nonces, so several can be in flight at
+/// once, and watches each until it is mined.
+pub struct TxManager {
+    provider: WalletProvider,
+    address: Address,
+    config: TxConfig,
+    /// The nonce of the next transaction, or `None` to read it from the node.
+    next_nonce: Mutex<Option<u64>>,
+}
+
+impl TxManager {
+    pub fn new(provider: WalletProvider, address: Address, config: TxConfig) -> Self {
+        Self { provider, address, config, next_nonce: Mutex::new(None) }
+    }
+
+    pub fn provider(&self) -> &WalletProvider {
+        &self.provider
+    }
+
+    pub async fn send(self: &Arc<Self>, tx: TransactionRequest) -> Result<PendingTx, TxError> {
+        let mut tx = tx.with_from(self.address);
+        // Estimated once, so that replacements do not revert in estimation when the original
+        // has already been mined.
+        let gas_limit = self.provider.estimate_gas(&tx).await?;
+        let fees = self.provider.estimate_eip1559_fees(None).await?;
+        tx.set_gas_limit(gas_limit);
+        tx.set_max_fee_per_gas(fees.max_fee_per_gas);
+        tx.set_max_priority_fee_per_gas(fees.max_priority_fee_per_gas);
+
+        let mut next_nonce = self.next_nonce.lock().await;
+        let tx_hash = match self.broadcast(&mut next_nonce, &mut tx).await {
+            Err(TxError::NonceConflict(message)) => {
+                log::warn!("Nonce conflict, retrying with the node's nonce: {}", message);
+                self.broadcast(&mut next_nonce, &mut tx).await?
+            }
+            result => result?,
+        };
+        drop(next_nonce);
+        log::info!("Sent tx {} with nonce {:?}", tx_hash, tx.nonce);
+
+        let (sender, receipt) = oneshot::channel();
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let _ = sender.send(manager.watch(tx, tx_hash, fees).await);
+        });
+        Ok(PendingTx { tx_hash: tx_hash.encode_hex_with_prefix(), receipt })
+    }
+
+    async fn broadcast(
+        &self,
+        next_nonce: &mut Option<u64>,
+        tx: &mut TransactionRequest,
+    ) -> Result<TxHash, TxError> {
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.provider.get_transaction_count(self.address).pending().await?,
+        };
+        tx.set_nonce(nonce);
+        match self.provider.send_transaction(tx.clone()).await {
+            Ok(pending) => {
+                *next_nonce = Some(nonce + 1);
+                Ok(*pending.tx_hash())
+            }
+            Err(e) => {
+                let error = TxError::from(e);
+                // After a transport error it is unknown whether the node took the nonce.
+                if matches!(error, TxError::Rpc(_) | TxError::NonceConflict(_)) {
+                    *next_nonce = None;
+                } else {
+                    *next_nonce = Some(nonce);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Resets the local nonce to the node's pending nonce after a timeout. That skips the nonce of
+    /// a transaction still in the mempool, but reuses the nonce of one the node dropped, which
+    /// later transactions would otherwise queue behind until a restart.
+    async fn resync_nonce(&self) {
+        let mut next_nonce = self.next_nonce.lock().await;
+        match self.provider.get_transaction_count(self.address).pending().await {
+            Ok(nonce) => {
+                if *next_nonce != Some(nonce) {
+                    log::warn!("Resynced nonce from {:?} to the node's {}", *next_nonce, nonce);
+                }
+                *next_nonce = Some(nonce);
+            }
+            Err(e) => {
+                log::warn!("Failed to resync nonce, reading it again on the next send: {:?}", e);
+                *next_nonce = None;
+            }
+        }
+    }
+
+    /// Polls for a receipt of the transaction or any of its replacements, bumping the fees each
+    /// time it has been stuck for `stuck_after`.
+    async fn watch(
+        &self,
+        mut tx: TransactionRequest,
+        tx_hash: TxHash,
+        mut fees: Eip1559Estimation,
+    ) -> Result<TransactionReceipt, TxError> {
+        let mut tx_hashes = vec![tx_hash];
+        let mut bumps = 0;
+        let mut sent_at = Instant::now();
+        loop {
+            sleep(self.config.poll_interval).await;
+            for tx_hash in &tx_hashes {
+                match self.provider.get_transaction_receipt(*tx_hash).await {
+                    Ok(Some(receipt)) if receipt.status() => return Ok(receipt),
+                    Ok(Some(_)) => {
+                        return Err(TxError::Reverted { tx_hash: tx_hash.encode_hex_with_prefix() })
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Failed to get receipt of tx {}: {:?}", tx_hash, e),
+                }
+            }
+            if sent_at.elapsed() < self.config.stuck_after {
+                continue;
+            }
+
+            let last_hash = *tx_hashes.last().expect("At least one tx was sent");
+            if bumps >= self.config.max_bumps {
+                self.resync_nonce().await;
+                return Err(TxError::Timeout { tx_hash: last_hash.encode_hex_with_prefix() });
+            }
+            bumps += 1;
+            sent_at = Instant::now();
+            let estimate = self.provider.estimate_eip1559_fees(None).await.ok();
+            fees = bumped_fees(&fees, estimate.as_ref(), self.config.gas_bump_percent);
+            tx.set_max_fee_per_gas(fees.max_fee_per_gas);
+            tx.set_max_priority_fee_per_gas(fees.max_priority_fee_per_gas);
+            match self.provider.send_transaction(tx.clone()).await {
+                Ok(pending) => {
+                    log::info!("Replaced stuck tx {} with {}", last_hash, pending.tx_hash());
+                    tx_hashes.push(*pending.tx_hash());
+                }
+                // The last transaction may have been mined since the receipts were polled.
+                Err(e) => log::warn!("Failed to replace stuck tx {}: {:?}", last_hash, e),
+            }
+        }
+    }
+}
+
+/// Raises both fee caps by `percent`, or to the current estimate if that is higher.
+fn bumped_fees(
+    fees: &Eip1559Estimation,
+    estimate: Option<&Eip1559Estimation>,
+    percent: u128,
+) -> Eip1559Estimation {
+    let bump = |fee: u128| fee.saturating_mul(100 + percent).div_ceil(100);
+    let mut bumped = Eip1559Estimation {
+        max_fee_per_gas: bump(fees.max_fee_per_gas),
+        max_priority_fee_per_gas: bump(fees.max_priority_fee_per_gas),
+    };
+    if let Some(estimate) = estimate {
+        bumped.max_fee_per_gas = bumped.max_fee_per_gas.max(estimate.max_fee_per_gas);
+        bumped.max_priority_fee_per_gas =
+            bumped.max_priority_fee_per_gas.max(estimate.max_priority_fee_per_gas);
+    }
+    bumped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bumped_fees_test() -> eyre::Result<()> {
+        let fees = Eip1559Estimation { max_fee_per_gas: 1_000, max_priority_fee_per_gas: 101 };
+        let bumped = bumped_fees(&fees, None, 20);
+        assert_eq!(bumped.max_fee_per_gas, 1_200);
+        // Rounds up so a small tip still clears the node's minimum bump.
+        assert_eq!(bumped.max_priority_fee_per_gas, 122);
+
+        let estimate = Eip1559Estimation { max_fee_per_gas: 5_000, max_priority_fee_per_gas: 50 };
+        let bumped = bumped_fees(&fees, Some(&estimate), 20);
+        assert_eq!(bumped.max_fee_per_gas, 5_000);
+        assert_eq!(bumped.max_priority_fee_per_gas, 122);
+        Ok(())
+    }
+
+    #[test]
+    fn tx_error_from_rejection_test() -> eyre::Result<()> {
+        assert!(matches!(
+            TxError::from_rejection("nonce too low: next nonce 7, tx nonce 5"),
+            TxError::NonceConflict(_)
+        ));
+        assert!(matches!(
+            TxError::from_rejection("replacement transaction underpriced"),
+            TxError::NonceConflict(_)
+        ));
+        assert_eq!(
+            TxError::from_rejection("insufficient funds for gas * price + value"),
+            TxError::InsufficientFunds
+        );
+        assert!(matches!(
+            TxError::from_rejection("execution reverted: ERC721: invalid token ID"),
+            TxError::WouldRevert(_)
+        ));
+        assert!(matches!(
+            TxError::from_rejection("transaction type not supported"),
+            TxError::Rejected(_)
+        ));
+        assert!(TxError::from_rejection("already known").is_retryable());
+        assert!(!TxError::Timeout { tx_hash: "0xabc".to_string() }.is_retryable());
+        Ok(())
+    }
+}
@@ -0,0 +1,747 @@
+//! A local stand-in for the parts of the X API that teleport uses, for running the login, mint and
+//! redeem flows offline. OAuth 1.0a signatures are verified against `TWITTER_CONSUMER_KEY` and
+//! `TWITTER_CONSUMER_SECRET` like X does. OAuth 2.0 logins are served for the client in
+//! `X_OAUTH2_CLIENT_ID` and `X_OAUTH2_CLIENT_SECRET`, if set, with PKCE checked. Listens on
+//! `MOCK_X_ADDR` (default `127.0.0.1:8787`).
+//!
+//! Besides the X endpoints it serves `GET /mock/state` to inspect what was posted,
+//! `POST /mock/revoke?token=` to revoke a user's access tokens, and `GET /mock/media/:file` with a
+//! small PNG or GIF to redeem as media.
+//!
+//! Point teleport at it by setting `X_API_URL`, `X_UPLOAD_URL` and `X_WEB_URL` to
+//! `http://127.0.0.1:8787`, and `MEDIA_LOCAL_HOSTS` to `127.0.0.1`.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+const USER_ID: &str = "1000";
+const USERNAME: &str = "mock_user";
+/// Characters OAuth 1.0a leaves unencoded (RFC 5849, section 3.6).
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+/// Lifetime of OAuth 2.0 access tokens, as on X.
+const OAUTH2_EXPIRES_IN: u64 = 7200;
+/// The smallest valid PNG, a single transparent pixel.
+const PNG: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1F, 0x15, 0xC4,
+    0x89, 0x00, 0x00, 0x00, 0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x00, 0x01, 0x00, 0x00,
+    0x05, 0x00, 0x01, 0x0D, 0x0A, 0x2D, 0xB4, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE,
+    0x42, 0x60, 0x82,
+];
+/// The smallest valid GIF, a single transparent pixel.
+const GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xFF, 0xFF, 0xFF, 0x21, 0xF9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2C, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3B,
+];
+
+struct RequestToken {
+    secret: String,
+    callback: String,
+    verifier: String,
+}
+
+/// An OAuth 2.0 authorization code waiting to be exchanged.
+struct AuthorizationCode {
+    redirect_uri: String,
+    code_challenge: String,
+}
+
+/// A registered OAuth 2.0 app.
+struct OAuth2Client {
+    client_id: String,
+    client_secret: Option<String>,
+}
+
+#[derive(Default, Serialize)]
+struct Posted {
+    tweets: Vec<Value>,
+    media_ids: Vec<String>,
+    likes: Vec<String>,
+    retweets: Vec<String>,
+}
+
+#[derive(Default)]
+struct MockData {
+    request_tokens: HashMap<String, RequestToken>,
+    /// Access token to secret.
+    access_tokens: HashMap<String, String>,
+    authorization_codes: HashMap<String, AuthorizationCode>,
+    oauth2_access_tokens: HashSet<String>,
+    oauth2_refresh_tokens: HashSet<String>,
+    posted: Posted,
+    next_id: u64,
+}
+
+impl MockData {
+    fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        (1_800_000_000_000_000_000 + self.next_id).to_string()
+    }
+}
+
+struct MockX {
+    consumer_key: String,
+    consumer_secret: String,
+    oauth2_client: Option<OAuth2Client>,
+    data: Mutex<MockData>,
+}
+
+type MockState = State<Arc<MockX>>;
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, UNRESERVED).to_string()
+}
+
+fn decode(value: &str) -> String {
+    percent_decode_str(value).decode_utf8_lossy().to_string()
+}
+
+fn error(status: StatusCode, detail: &str) -> Response {
+    (status, Json(json!({ "title": status.canonical_reason(), "detail": detail }))).into_response()
+}
+
+fn oauth_params(headers: &HeaderMap) -> Option<BTreeMap<String, String>> {
+    let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let params = authorization.strip_prefix("OAuth ")?;
+    Some(
+        params
+            .split(',')
+            .filter_map(|param| param.trim().split_once('='))
+            .map(|(key, value)| (decode(key), decode(value.trim_matches('"'))))
+            .collect(),
+    )
+}
+
+/// The HMAC-SHA1 signature of a request (RFC 5849, section 3.4).
+fn signature(
+    method: &Method,
+    base_url: &str,
+    params: &[(String, String)],
+    consumer_secret: &str,
+    token_secret: &str,
+) -> String {
+    let mut encoded =
+        params.iter().map(|(key, value)| (encode(key), encode(value))).collect::<Vec<_>>();
+    encoded.sort();
+    let param_string =
+        encoded.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>();
+    let base_string =
+        format!("{}&{}&{}", method.as_str(), encode(base_url), encode(&param_string.join("&")));
+    let key = format!("{}&{}", encode(consumer_secret), encode(token_secret));
+    let mut mac = Hmac::<Sha1>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key");
+    mac.update(base_string.as_bytes());
+    STANDARD.encode(mac.finalize().into_bytes())
+}
+
+impl MockX {
+    /// Checks the request's OAuth 1.0a signature and returns its token, if any. `token_secret`
+    /// looks up the secret for the token the request was signed with.
+    fn verify(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+        token_secret: impl FnOnce(&MockData, &str) -> Option<String>,
+    ) -> Result<Option<String>, Response> {
+        let unauthorized = || error(StatusCode::UNAUTHORIZED, "Unauthorized");
+        let oauth = oauth_params(headers).ok_or_else(unauthorized)?;
+        if oauth.get("oauth_consumer_key") != Some(&self.consumer_key) ||
+            oauth.get("oauth_signature_method").map(String::as_str) != Some("HMAC-SHA1")
+        {
+            return Err(unauthorized());
+        }
+        let token = oauth.get("oauth_token").cloned();
+        let secret = match &token {
+            Some(token) => {
+                token_secret(&self.data.lock().unwrap(), token).ok_or_else(unauthorized)?
+            }
+            None => String::new(),
+        };
+
+        let mut params = oauth
+            .iter()
+            .filter(|(key, _)| *key != "oauth_signature" && *key != "realm")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        params
+            .extend(url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes()).into_owned());
+        let is_form = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+        if is_form {
+            params.extend(url::form_urlencoded::parse(body).into_owned());
+        }
+
+        let host = headers.get(header::HOST).and_then(|value| value.to_str().ok()).unwrap_or("");
+        let base_url = format!("http://{}{}", host.to_lowercase(), uri.path());
+        let expected = signature(method, &base_url, &params, &self.consumer_secret, &secret);
+        if oauth.get("oauth_signature") != Some(&expected) {
+            log::warn!("Bad signature for {} {}", method, uri);
+            return Err(unauthorized());
+        }
+        Ok(token)
+    }
+
+    /// Checks that the request is made for a user, with either an OAuth 2.0 bearer token or an
+    /// OAuth 1.0a signature.
+    fn verify_user(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<(), Response> {
+        let authorization =
+            headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+        if let Some(access_token) = authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+            if !self.data.lock().unwrap().oauth2_access_tokens.contains(access_token) {
+                return Err(error(StatusCode::UNAUTHORIZED, "Unauthorized"));
+            }
+            return Ok(());
+        }
+        self.verify(method, uri, headers, body, |data, token| {
+            data.access_tokens.get(token).cloned()
+        })
+        .map(|_| ())
+    }
+}
+
+fn form_response(pairs: &[(&str, &str)]) -> Response {
+    let body = url::form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs).finish();
+    ([(header::CONTENT_TYPE, "application/x-www-form-urlencoded")], body).into_response()
+}
+
+async fn request_token(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(response) = mock.verify(&method, &uri, &headers, &body, |_, _| None) {
+        return response;
+    }
+    let query = url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    let Some(callback) = query.get("oauth_callback") else {
+        return error(StatusCode::BAD_REQUEST, "Missing oauth_callback");
+    };
+    let (token, secret) = (cuid::cuid2(), cuid::cuid2());
+    mock.data.lock().unwrap().request_tokens.insert(
+        token.clone(),
+        RequestToken {
+            secret: secret.clone(),
+            callback: callback.clone(),
+            verifier: cuid::cuid2(),
+        },
+    );
+    form_response(&[
+        ("oauth_token", &token),
+        ("oauth_token_secret", &secret),
+        ("oauth_callback_confirmed", "true"),
+    ])
+}
+
+#[derive(Deserialize)]
+struct AuthenticateQuery {
+    oauth_token: String,
+}
+
+/// Approves the login straight away, as if the user clicked "Authorize app".
+async fn authenticate(State(mock): MockState, Query(query): Query<AuthenticateQuery>) -> Response {
+    let data = mock.data.lock().unwrap();
+    let Some(request_token) = data.request_tokens.get(&query.oauth_token) else {
+        return error(StatusCode::BAD_REQUEST, "Unknown oauth_token");
+    };
+    let separator = if request_token.callback.contains('?') { '&' } else { '?' };
+    let url = format!(
+        "{}{}oauth_token={}&oauth_verifier={}",
+        request_token.callback, separator, query.oauth_token, request_token.verifier
+    );
+    Redirect::temporary(&url).into_response()
+}
+
+async fn access_token(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let token = match mock.verify(&method, &uri, &headers, &body, |data, token| {
+        data.request_tokens.get(token).map(|request_token| request_token.secret.clone())
+    }) {
+        Ok(Some(token)) => token,
+        Ok(None) => return error(StatusCode::UNAUTHORIZED, "Missing oauth_token"),
+        Err(response) => return response,
+    };
+    let query = url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    let mut data = mock.data.lock().unwrap();
+    let request_token = data.request_tokens.remove(&token).expect("Token was just verified");
+    if query.get("oauth_verifier") != Some(&request_token.verifier) {
+        return error(StatusCode::UNAUTHORIZED, "Invalid oauth_verifier");
+    }
+    let (access_token, access_secret) = (cuid::cuid2(), cuid::cuid2());
+    data.access_tokens.insert(access_token.clone(), access_secret.clone());
+    drop(data);
+    form_response(&[
+        ("oauth_token", &access_token),
+        ("oauth_token_secret", &access_secret),
+        ("user_id", USER_ID),
+        ("screen_name", USERNAME),
+    ])
+}
+
+#[derive(Deserialize)]
+struct OAuth2AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    code_challenge: String,
+    code_challenge_method: String,
+}
+
+/// Approves the OAuth 2.0 login straight away, as if the user clicked "Authorize app".
+async fn oauth2_authorize(
+    State(mock): MockState,
+    Query(query): Query<OAuth2AuthorizeQuery>,
+) -> Response {
+    let Some(client) = &mock.oauth2_client else {
+        return error(StatusCode::BAD_REQUEST, "OAuth 2.0 is not configured");
+    };
+    if query.client_id != client.client_id {
+        return error(StatusCode::BAD_REQUEST, "Unknown client_id");
+    }
+    if query.code_challenge_method != "S256" {
+        return error(StatusCode::BAD_REQUEST, "Unsupported code_challenge_method");
+    }
+    let code = cuid::cuid2();
+    mock.data.lock().unwrap().authorization_codes.insert(
+        code.clone(),
+        AuthorizationCode {
+            redirect_uri: query.redirect_uri.clone(),
+            code_challenge: query.code_challenge,
+        },
+    );
+    let Ok(url) = url::Url::parse_with_params(
+        &query.redirect_uri,
+        &[("code", code.as_str()), ("state", query.state.as_str())],
+    ) else {
+        return error(StatusCode::BAD_REQUEST, "Invalid redirect_uri");
+    };
+    Redirect::temporary(url.as_str()).into_response()
+}
+
+fn oauth2_error(description: &str) -> Response {
+    let body = json!({ "error": "invalid_request", "error_description": description });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// Exchanges an authorization code or a refresh token for new tokens. Like X, it rotates the
+/// refresh token.
+async fn oauth2_token(State(mock): MockState, headers: HeaderMap, body: Bytes) -> Response {
+    let Some(client) = &mock.oauth2_client else {
+        return error(StatusCode::BAD_REQUEST, "OAuth 2.0 is not configured");
+    };
+    if let Some(client_secret) = &client.client_secret {
+        let credentials = STANDARD.encode(format!("{}:{}", client.client_id, client_secret));
+        let authorization =
+            headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+        if authorization != Some(format!("Basic {}", credentials).as_str()) {
+            return error(StatusCode::UNAUTHORIZED, "Unauthorized");
+        }
+    }
+    let form = url::form_urlencoded::parse(&body).into_owned().collect::<HashMap<_, _>>();
+    let param = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    if param("client_id") != client.client_id {
+        return oauth2_error("Unknown client_id");
+    }
+
+    let mut data = mock.data.lock().unwrap();
+    match param("grant_type") {
+        "authorization_code" => {
+            let Some(code) = data.authorization_codes.remove(param("code")) else {
+                return oauth2_error("Value passed for the authorization code was invalid.");
+            };
+            let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(param("code_verifier")));
+            if code.redirect_uri != param("redirect_uri") || code.code_challenge != challenge {
+                return oauth2_error("Value passed for the authorization code was invalid.");
+            }
+        }
+        "refresh_token" => {
+            if !data.oauth2_refresh_tokens.remove(param("refresh_token")) {
+                return oauth2_error("Value passed for the token was invalid.");
+            }
+        }
+        _ => return oauth2_error("Unsupported grant_type"),
+    }
+    let (access_token, refresh_token) = (cuid::cuid2(), cuid::cuid2());
+    data.oauth2_access_tokens.insert(access_token.clone());
+    data.oauth2_refresh_tokens.insert(refresh_token.clone());
+    Json(json!({
+        "token_type": "bearer",
+        "expires_in": OAUTH2_EXPIRES_IN,
+        "access_token": access_token,
+        "scope": "tweet.read tweet.write like.write users.read offline.access",
+        "refresh_token": refresh_token,
+    }))
+    .into_response()
+}
+
+async fn users_me(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(response) = mock.verify_user(&method, &uri, &headers, &body) {
+        return response;
+    }
+    let host = headers.get(header::HOST).and_then(|value| value.to_str().ok()).unwrap_or("");
+    Json(json!({
+        "data": {
+            "id": USER_ID,
+            "name": "Mock User",
+            "username": USERNAME,
+            "profile_image_url": format!("http://{}/pfp_normal.png", host),
+        }
+    }))
+    .into_response()
+}
+
+async fn create_tweet(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(response) = mock.verify_user(&method, &uri, &headers, &body) {
+        return response;
+    }
+    let Ok(mut tweet) = serde_json::from_slice::<Value>(&body) else {
+        return error(StatusCode::BAD_REQUEST, "Invalid JSON");
+    };
+    let Some(text) = tweet["text"].as_str().map(str::to_string) else {
+        return error(StatusCode::BAD_REQUEST, "Missing text");
+    };
+    let mut data = mock.data.lock().unwrap();
+    if data.posted.tweets.iter().any(|posted| posted["text"] == text) {
+        return error(
+            StatusCode::FORBIDDEN,
+            "You are not allowed to create a Tweet with duplicate content.",
+        );
+    }
+    if let Some(media_ids) = tweet["media"]["media_ids"].as_array() {
+        let known = media_ids.iter().all(|id| {
+            id.as_str().is_some_and(|id| data.posted.media_ids.iter().any(|known| known == id))
+        });
+        if !known {
+            return error(StatusCode::BAD_REQUEST, "Unknown media id");
+        }
+    }
+    let id = data.next_id();
+    tweet["id"] = json!(id);
+    data.posted.tweets.push(tweet);
+    Json(json!({ "data": { "id": id, "text": text } })).into_response()
+}
+
+async fn get_tweet(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    if let Err(response) = mock.verify_user(&method, &uri, &headers, &[]) {
+        return response;
+    }
+    let data = mock.data.lock().unwrap();
+    match data.posted.tweets.iter().find(|tweet| tweet["id"] == id) {
+        Some(tweet) => Json(json!({ "data": { "id": id, "text": tweet["text"] } })).into_response(),
+        None => error(StatusCode::NOT_FOUND, "Could not find tweet"),
+    }
+}
+
+/// The named fields of a `multipart/form-data` body.
+fn multipart_fields(headers: &HeaderMap, body: &[u8]) -> Option<HashMap<String, Vec<u8>>> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let boundary = content_type.split("boundary=").nth(1)?.trim_matches('"');
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut fields = HashMap::new();
+    let mut rest = body;
+    while let Some(start) = find(rest, &delimiter) {
+        rest = &rest[start + delimiter.len()..];
+        if rest.starts_with(b"--") {
+            break;
+        }
+        let headers_end = find(rest, b"\r\n\r\n")?;
+        let part_headers = String::from_utf8_lossy(&rest[..headers_end]).to_string();
+        let content = &rest[headers_end + 4..];
+        let content_end = find(content, &delimiter)?;
+        let name = part_headers.split("name=\"").nth(1)?.split('"').next()?.to_string();
+        // Each part ends with a CRLF before the next delimiter.
+        fields.insert(name, content[..content_end.saturating_sub(2)].to_vec());
+        rest = &content[content_end..];
+    }
+    Some(fields)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn media_response(media_id: &str, state: Option<&str>) -> Response {
+    let mut response =
+        json!({ "media_id": media_id.parse::<u64>().ok(), "media_id_string": media_id });
+    if let Some(state) = state {
+        response["processing_info"] = json!({ "state": state, "check_after_secs": 0 });
+    }
+    Json(response).into_response()
+}
+
+async fn upload_media(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(response) = mock.verify_user(&method, &uri, &headers, &body) {
+        return response;
+    }
+    let mut params = url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    let mut media = None;
+    if let Some(fields) = multipart_fields(&headers, &body) {
+        for (name, value) in fields {
+            if name == "media" {
+                media = Some(value);
+            } else {
+                params.insert(name, String::from_utf8_lossy(&value).to_string());
+            }
+        }
+    } else {
+        params.extend(url::form_urlencoded::parse(&body).into_owned());
+    }
+
+    let mut data = mock.data.lock().unwrap();
+    let media_id = params.get("media_id").cloned().unwrap_or_default();
+    match params.get("command").map(String::as_str) {
+        None if media.as_ref().is_some_and(|media| !media.is_empty()) => {
+            let media_id = data.next_id();
+            data.posted.media_ids.push(media_id.clone());
+            media_response(&media_id, None)
+        }
+        Some("INIT") => media_response(&data.next_id(), None),
+        Some("APPEND") if media.is_some() => StatusCode::NO_CONTENT.into_response(),
+        // Report processing once so that clients exercise STATUS polling.
+        Some("FINALIZE") => media_response(&media_id, Some("pending")),
+        Some("STATUS") => {
+            data.posted.media_ids.push(media_id.clone());
+            media_response(&media_id, Some("succeeded"))
+        }
+        _ => error(StatusCode::BAD_REQUEST, "Invalid media upload"),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReactionBody {
+    tweet_id: String,
+}
+
+async fn react(
+    mock: &MockX,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    user_id: String,
+    body: Bytes,
+    retweet: bool,
+) -> Response {
+    if let Err(response) = mock.verify_user(&method, &uri, &headers, &body) {
+        return response;
+    }
+    if user_id != USER_ID {
+        return error(StatusCode::FORBIDDEN, "You can only act as the authenticated user");
+    }
+    let Ok(reaction) = serde_json::from_slice::<ReactionBody>(&body) else {
+        return error(StatusCode::BAD_REQUEST, "Missing tweet_id");
+    };
+    let mut data = mock.data.lock().unwrap();
+    if retweet {
+        data.posted.retweets.push(reaction.tweet_id);
+        Json(json!({ "data": { "retweeted": true } })).into_response()
+    } else {
+        data.posted.likes.push(reaction.tweet_id);
+        Json(json!({ "data": { "liked": true } })).into_response()
+    }
+}
+
+async fn like(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(user_id): Path<String>,
+    body: Bytes,
+) -> Response {
+    react(&mock, method, uri, headers, user_id, body, false).await
+}
+
+async fn retweet(
+    State(mock): MockState,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Path(user_id): Path<String>,
+    body: Bytes,
+) -> Response {
+    react(&mock, method, uri, headers, user_id, body, true).await
+}
+
+async fn state(State(mock): MockState) -> Response {
+    Json(&mock.data.lock().unwrap().posted).into_response()
+}
+
+#[derive(Deserialize)]
+struct RevokeQuery {
+    token: String,
+}
+
+async fn revoke(State(mock): MockState, Query(query): Query<RevokeQuery>) -> StatusCode {
+    let mut data = mock.data.lock().unwrap();
+    let revoked = data.access_tokens.remove(&query.token).is_some() ||
+        data.oauth2_access_tokens.remove(&query.token);
+    if revoked {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Serves a tiny image or GIF, by file extension, for redeems with media.
+async fn media(Path(file): Path<String>) -> Response {
+    let (content_type, bytes) = match file.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("png") => ("image/png", PNG),
+        Some("gif") => ("image/gif", GIF),
+        _ => return error(StatusCode::NOT_FOUND, "Unknown media"),
+    };
+    ([(header::CONTENT_TYPE, content_type)], bytes).into_response()
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    env_logger::init();
+
+    let consumer_key = std::env::var("TWITTER_CONSUMER_KEY").expect("TWITTER_CONSUMER_KEY not set");
+    let consumer_secret =
+        std::env::var("TWITTER_CONSUMER_SECRET").expect("TWITTER_CONSUMER_SECRET not set");
+    let addr = std::env::var("MOCK_X_ADDR").unwrap_or("127.0.0.1:8787".to_string());
+
+    let oauth2_client = std::env::var("X_OAUTH2_CLIENT_ID").ok().map(|client_id| OAuth2Client {
+        client_id,
+        client_secret: std::env::var("X_OAUTH2_CLIENT_SECRET").ok(),
+    });
+
+    let mock =
+        Arc::new(MockX { consumer_key, consumer_secret, oauth2_client, data: Default::default() });
+    let app = Router::new()
+        .route("/oauth/request_token", post(request_token))
+        .route("/oauth/authenticate", get(authenticate))
+        .route("/oauth/access_token", post(access_token))
+        .route("/i/oauth2/authorize", get(oauth2_authorize))
+        .route("/2/oauth2/token", post(oauth2_token))
+        .route("/2/users/me", get(users_me))
+        .route("/2/tweets", post(create_tweet))
+        .route("/2/tweets/:id", get(get_tweet))
+        .route("/1.1/media/upload.json", get(upload_media).post(upload_media))
+        .route("/2/users/:id/likes", post(like))
+        .route("/2/users/:id/retweets", post(retweet))
+        .route("/mock/state", get(state))
+        .route("/mock/revoke", post(revoke))
+        .route("/mock/media/:file", get(media))
+        .with_state(mock);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.expect("Failed to bind MOCK_X_ADDR");
+    log::info!("Mock X API listening on {}", addr);
+    axum::serve(listener, app).await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_test() {
+        // The example from appendix A of the OAuth Core 1.0 spec.
+        let params = [
+            ("file", "vacation.jpg"),
+            ("size", "original"),
+            ("oauth_consumer_key", "dpf43f3p2l4k3l03"),
+            ("oauth_token", "nnch734d00sl2jdk"),
+            ("oauth_signature_method", "HMAC-SHA1"),
+            ("oauth_timestamp", "1191242096"),
+            ("oauth_nonce", "kllo9940pd9333jh"),
+            ("oauth_version", "1.0"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        let sign = |params: &[(String, String)]| {
+            signature(
+                &Method::GET,
+                "http://photos.example.net/photos",
+                params,
+                "kd94hf93k423kf44",
+                "pfkkdhi9sl3r4s00",
+            )
+        };
+        assert_eq!(sign(&params), "tR3+Ty81lMeYAr/Fid0kMTYa/WM=");
+        let mut tampered = params.clone();
+        tampered[1].1 = "thumbnail".to_string();
+        assert_ne!(sign(&tampered), "tR3+Ty81lMeYAr/Fid0kMTYa/WM=");
+
+        assert_eq!(encode("r b"), "r%20b");
+        assert_eq!(encode("=%3D"), "%3D%253D");
+        assert_eq!(encode("a-._~"), "a-._~");
+    }
+
+    #[test]
+    fn multipart_fields_test() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, "multipart/form-data; boundary=xyz".parse().unwrap());
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"command\"\r\n\r\nAPPEND\r\n--xyz\r\nContent-Disposition: form-data; name=\"media\"\r\n\r\n\x00\x01\r\n--xyz--\r\n";
+        let fields = multipart_fields(&headers, body).unwrap();
+        assert_eq!(fields["command"], b"APPEND");
+        assert_eq!(fields["media"], vec![0, 1]);
+    }
+}
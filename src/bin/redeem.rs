@@ -12,7 +12,7 @@ use alloy::{
     signers::local::{coins_bip39::English, MnemonicBuilder},
     sol,
 };
-use serde::Serialize;
+use teleport::content::RedeemContent;
 
 sol!(
     #[sol(rpc)]
@@ -20,12 +20,6 @@ sol!(
     "abi/redeem.json"
 );
 
-#[derive(Serialize)]
-struct TweetContent {
-    text: String,
-    media_url: Option<String>,
-}
-
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
@@ -59,12 +53,12 @@ async fn main() {
         provider,
     );
 
-    let tweet_content = TweetContent {
-        text: "libmev mevbot take 2: batchredeem with image".to_string(),
-        media_url: Some("https://i.imgur.com/HLHBnl9.jpeg".to_string()),
+    let tweet_content = RedeemContent {
+        media_urls: vec!["https://i.imgur.com/HLHBnl9.jpeg".to_string()],
+        ..RedeemContent::new("libmev mevbot take 2: batchredeem with image".to_string())
     };
 
-    let content = serde_json::to_string(&tweet_content).unwrap();
+    let content = tweet_content.encode().unwrap();
 
     let token_ids = (244..340).map(|i| Uint::from(i)).collect::<Vec<_>>();
 
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::twitter::tweet::Tweet;
+
+/// The current version of the redeem content envelope.
+pub const CONTENT_VERSION: u64 = 2;
+const MAX_MEDIA: usize = 4;
+
+/// What a redeemed tweet token posts, as stored in the on-chain `content` string.
+///
+/// Content is a JSON object with a `"v"` key holding a versioned envelope, or else plain tweet
+/// text, so a tweet that happens to be other JSON is posted as is. An object with a `"text"` key
+/// but no version is the original v1 envelope (see [`LegacyContent`]). An envelope that does not
+/// match its version exactly is rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedeemContent {
+    pub v: u64,
+    pub text: String,
+    /// Up to four images, or a single GIF or video.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media_urls: Vec<String>,
+    /// Id of the tweet to reply to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// Id of the tweet to quote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_of: Option<String>,
+}
+
+/// The unversioned v1 envelope, `{"text", "media_url"}`, still found in older redeems.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyContent {
+    text: String,
+    #[serde(default)]
+    media_url: Option<String>,
+}
+
+impl From<LegacyContent> for RedeemContent {
+    fn from(legacy: LegacyContent) -> Self {
+        Self {
+            media_urls: legacy.media_url.into_iter().filter(|url| !url.is_empty()).collect(),
+            ..Self::new(legacy.text)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferencedTweet<'a> {
+    Reply(&'a str),
+    Quote(&'a str),
+}
+
+impl RedeemContent {
+    pub fn new(text: String) -> Self {
+        Self { v: CONTENT_VERSION, text, media_urls: vec![], reply_to: None, quote_of: None }
+    }
+
+    pub fn parse(content: &str) -> eyre::Result<Self> {
+        let envelope = match serde_json::from_str::<serde_json::Value>(content) {
+            Ok(serde_json::Value::Object(object))
+                if object.contains_key("v") || object.contains_key("text") =>
+            {
+                object
+            }
+            _ => return Ok(Self::new(content.to_string())),
+        };
+        let content: Self = match envelope.get("v").map(|v| v.as_u64()) {
+            None => serde_json::from_value::<LegacyContent>(serde_json::Value::Object(envelope))
+                .map(Self::from),
+            Some(Some(CONTENT_VERSION)) => {
+                serde_json::from_value(serde_json::Value::Object(envelope))
+            }
+            _ => eyre::bail!("Unsupported redeem content version: {}", envelope["v"]),
+        }
+        .map_err(|e| eyre::eyre!("Malformed redeem content: {}", e))?;
+        content.validate()?;
+        Ok(content)
+    }
+
+    pub fn validate(&self) -> eyre::Result<()> {
+        if self.v != CONTENT_VERSION {
+            eyre::bail!("Unsupported redeem content version: {}", self.v);
+        }
+        if self.media_urls.len() > MAX_MEDIA {
+            eyre::bail!("Redeem content can have at most {} media", MAX_MEDIA);
+        }
+        self.referenced_tweet()?;
+        Ok(())
+    }
+
+    /// Serializes the content for the on-chain `content` string. Plain text stays plain.
+    pub fn encode(&self) -> eyre::Result<String> {
+        if Self::parse(&self.text).is_ok_and(|parsed| parsed == *self) {
+            return Ok(self.text.clone());
+        }
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Builds the tweet to post, without media, checking it against X's rules so that bad content
+    /// is rejected before the token is redeemed.
+    pub fn tweet(&self) -> eyre::Result<Tweet> {
+        let mut tweet = Tweet::new(self.text.clone());
+        match self.referenced_tweet()? {
+            Some(ReferencedTweet::Reply(tweet_id)) => {
+                tweet.set_reply_tweet_id(tweet_id.to_string())
+            }
+            Some(ReferencedTweet::Quote(tweet_id)) => {
+                tweet.set_quote_tweet_id(tweet_id.to_string())
+            }
+            None => {}
+        }
+        tweet.validate()?;
+        Ok(tweet)
+    }
+
+    /// The tweet this one replies to or quotes, if any.
+    pub fn referenced_tweet(&self) -> eyre::Result<Option<ReferencedTweet>> {
+        let referenced = match (&self.reply_to, &self.quote_of) {
+            (Some(_), Some(_)) => eyre::bail!("Tweet cannot be both a quote and a reply"),
+            (Some(reply_to), None) => ReferencedTweet::Reply(reply_to),
+            (None, Some(quote_of)) => ReferencedTweet::Quote(quote_of),
+            (None, None) => return Ok(None),
+        };
+        let (ReferencedTweet::Reply(tweet_id) | ReferencedTweet::Quote(tweet_id)) = referenced;
+        if tweet_id.is_empty() || !tweet_id.chars().all(|c| c.is_ascii_digit()) {
+            eyre::bail!("Invalid tweet id: {:?}", tweet_id);
+        }
+        Ok(Some(referenced))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redeem_content_test() -> eyre::Result<()> {
+        assert_eq!(RedeemContent::parse("gm")?, RedeemContent::new("gm".to_string()));
+        // Other JSON is just tweet text.
+        assert_eq!(RedeemContent::parse("[1, 2]")?.text, "[1, 2]");
+        assert_eq!(RedeemContent::parse(r#"{"gm": 1}"#)?.text, r#"{"gm": 1}"#);
+
+        let content = RedeemContent::parse(
+            r#"{"v": 2, "text": "gm", "media_urls": ["https://i.imgur.com/a.png"], "reply_to": "123"}"#,
+        )?;
+        assert_eq!(content.media_urls, vec!["https://i.imgur.com/a.png"]);
+        assert_eq!(content.referenced_tweet()?, Some(ReferencedTweet::Reply("123")));
+        assert_eq!(RedeemContent::parse(&content.encode()?)?, content);
+        assert!(content.tweet().is_ok());
+        assert!(RedeemContent::new("a".repeat(281)).tweet().is_err());
+
+        assert!(RedeemContent::parse(r#"{"v": 3, "text": "gm"}"#).is_err());
+        assert!(RedeemContent::parse(r#"{"v": "2", "text": "gm"}"#).is_err());
+        assert!(RedeemContent::parse(r#"{"v": 2}"#).is_err());
+        assert!(RedeemContent::parse(r#"{"v": 2, "text": "gm", "media_url": "x"}"#).is_err());
+        assert!(RedeemContent::parse(
+            r#"{"v": 2, "text": "gm", "reply_to": "1", "quote_of": "2"}"#
+        )
+        .is_err());
+        assert!(RedeemContent::parse(r#"{"v": 2, "text": "gm", "quote_of": "1 OR 1=1"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_redeem_content_test() -> eyre::Result<()> {
+        let content =
+            RedeemContent::parse(r#"{"text": "gm", "media_url": "https://i.imgur.com/a.png"}"#)?;
+        assert_eq!(content.v, CONTENT_VERSION);
+        assert_eq!(content.text, "gm");
+        assert_eq!(content.media_urls, vec!["https://i.imgur.com/a.png"]);
+        assert_eq!(
+            RedeemContent::parse(r#"{"text": "gm"}"#)?,
+            RedeemContent::new("gm".to_string())
+        );
+        assert_eq!(
+            RedeemContent::parse(r#"{"text": "gm", "media_url": ""}"#)?,
+            RedeemContent::new("gm".to_string())
+        );
+
+        assert!(RedeemContent::parse(r#"{"text": "gm", "media_urls": ["x"]}"#).is_err());
+        assert!(RedeemContent::parse(r#"{"text": 1}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode_test() -> eyre::Result<()> {
+        assert_eq!(RedeemContent::new("gm".to_string()).encode()?, "gm");
+        // Text that would be read as an envelope is wrapped in one.
+        let tricky = RedeemContent::new(r#"{"v": 2, "text": "hi"}"#.to_string());
+        assert_eq!(RedeemContent::parse(&tricky.encode()?)?, tricky);
+        Ok(())
+    }
+}
@@ -1,13 +1,25 @@
+use std::collections::HashMap;
+
 use rustls::ClientConfig;
+use serde::{Deserialize, Serialize};
 use tokio_postgres::Client;
 use tokio_postgres_rustls::MakeRustlsConnect;
 
+use super::{RedemptionStatus, TokenType};
+
+/// Changes to the frontend's schema that this service depends on, applied by
+/// [`ClientDB::migrate`] at startup. Each one must be safe to run again.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE \"RedeemedIndex\" ADD COLUMN IF NOT EXISTS \"status\" TEXT NOT NULL DEFAULT 'posted'",
+    "ALTER TABLE \"RedeemedIndex\" ADD COLUMN IF NOT EXISTS \"tokenType\" TEXT NOT NULL DEFAULT 'tweet'",
+];
+
 #[derive(Clone)]
 pub struct ClientDB {
     database_url: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TokenOwner {
     pub user_id: String,
     pub twitter_user_name: String,
@@ -31,6 +43,16 @@ impl ClientDB {
         Ok(client)
     }
 
+    /// Adds the columns this service writes to the frontend's tables if they are missing.
+    pub async fn migrate(&self) -> eyre::Result<()> {
+        let client = self.client().await?;
+        for migration in MIGRATIONS {
+            client.batch_execute(migration).await?;
+        }
+        log::info!("Applied {} client database migrations", MIGRATIONS.len());
+        Ok(())
+    }
+
     pub async fn get_token_owner(&self, token_id: String) -> eyre::Result<TokenOwner> {
         let token_id_int: i32 = token_id.parse()?;
         let token_owner = self
@@ -44,24 +66,82 @@ impl ClientDB {
         Ok(TokenOwner { user_id: token_owner.get(0), twitter_user_name: token_owner.get(1) })
     }
 
+    /// The creator and policy of each of the tokens, in one query. Tokens that are not indexed
+    /// are left out.
+    pub async fn get_token_owners_and_policies(
+        &self,
+        token_ids: &[String],
+    ) -> eyre::Result<HashMap<String, (TokenOwner, String)>> {
+        let token_ids_int =
+            token_ids.iter().map(|token_id| token_id.parse()).collect::<Result<Vec<i32>, _>>()?;
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT \"tokenId\", \"userId\", \"twitterUserName\", \"safeguard\" FROM \"NftIndex\" WHERE \"tokenId\" = ANY($1)",
+                &[&token_ids_int],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let token_id: i32 = row.get(0);
+                let token_owner = TokenOwner { user_id: row.get(1), twitter_user_name: row.get(2) };
+                (token_id.to_string(), (token_owner, row.get(3)))
+            })
+            .collect())
+    }
+
+    pub async fn get_token_policy(&self, token_id: String) -> eyre::Result<String> {
+        let token_id_int: i32 = token_id.parse()?;
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "SELECT \"safeguard\" FROM \"NftIndex\" WHERE \"tokenId\" = $1",
+                &[&token_id_int],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
     pub async fn add_redeemed_tweet(
         &self,
         token_owner: TokenOwner,
         token_id: String,
         content: String,
         safeguard: String,
+        status: RedemptionStatus,
+        token_type: TokenType,
+        tweet_id: String,
     ) -> eyre::Result<()> {
         let token_id_int: i32 = token_id.parse()?;
         let id = cuid::cuid2();
 
         self.client().await?.execute(
-            "INSERT INTO \"RedeemedIndex\" (\"id\", \"creatorUserId\", \"tokenId\", \"tweetId\", \"twitterUserName\", \"safeguard\", \"content\") VALUES ($1, $2, $3, $4, $5, $6, $7)",
-            &[&id, &token_owner.user_id, &token_id_int, &"".to_string(), &token_owner.twitter_user_name, &safeguard, &content],
+            "INSERT INTO \"RedeemedIndex\" (\"id\", \"creatorUserId\", \"tokenId\", \"tweetId\", \"twitterUserName\", \"safeguard\", \"content\", \"status\", \"tokenType\") VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[&id, &token_owner.user_id, &token_id_int, &tweet_id, &token_owner.twitter_user_name, &safeguard, &content, &status.as_str(), &token_type.as_str()],
         )
         .await?;
         Ok(())
     }
 
+    pub async fn set_redemption_status(
+        &self,
+        token_id: String,
+        status: RedemptionStatus,
+    ) -> eyre::Result<()> {
+        let token_id_int: i32 = token_id.parse()?;
+        self.client()
+            .await?
+            .execute(
+                "UPDATE \"RedeemedIndex\" SET \"status\" = $1 WHERE \"tokenId\" = $2",
+                &[&status.as_str(), &token_id_int],
+            )
+            .await?;
+        Ok(())
+    }
+
     pub async fn increment_user_redeemed(&self, user_id: String) -> eyre::Result<()> {
         self.client().await?
             .execute(
@@ -99,7 +179,7 @@ impl ClientDB {
         self.client()
             .await?
             .execute(
-                "INSERT INTO \"NftIndex\" (\"id\", \"userId\", \"tokenId\", \"twitterName\", \"twitterUserName\", \"twitterPfpUrl\", \"safeguard\", \"updatedAt\") VALUES ($1,$2,$3,$4,$5,$6,$7,NOW())",
+                "INSERT INTO \"NftIndex\" (\"id\", \"userId\", \"tokenId\", \"twitterName\", \"twitterUserName\", \"twitterPfpUrl\", \"safeguard\", \"updatedAt\") VALUES ($1,$2,$3,$4,$5,$6,$7,NOW()) ON CONFLICT DO NOTHING",
                 &[&nft_id,&address,&token_id_int,&twitter_name,&username,&pfp,&policy],
             )
             .await?;
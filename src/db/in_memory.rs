@@ -6,7 +6,11 @@ use std::{
     path::Path,
 };
 
-use super::{PendingNFT, Session, TeleportDB, User, NFT};
+use super::{PendingNFT, Redemption, Session, TeleportDB, User, NFT};
+use crate::twitter::oauth2::OAuth2Tokens;
+
+/// Where users are saved, one JSON file per address.
+pub const USERS_DIR: &str = "shared/users";
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct InMemoryDB {
@@ -15,6 +19,11 @@ pub struct InMemoryDB {
     pub pending_nfts: BTreeMap<String, PendingNFT>,
     pub nfts: BTreeMap<String, NFT>,
     pub tweets: BTreeMap<String, String>,
+    /// Tweets posted for a batch redeem, by job group.
+    pub batch_tweets: BTreeMap<String, String>,
+    /// Next redeem authorization nonce of each token owner.
+    pub redeem_nonces: BTreeMap<String, u64>,
+    pub redemptions: BTreeMap<String, Redemption>,
     pub sessions: BTreeMap<String, Session>,
 }
 
@@ -25,6 +34,21 @@ impl InMemoryDB {
     pub fn deserialize(data: &[u8]) -> Self {
         bincode::deserialize(data).expect("Failed to deserialize InMemoryUserDB")
     }
+
+    fn update_user_by_x_id(
+        &mut self,
+        x_id: String,
+        update: impl FnOnce(&mut User),
+    ) -> eyre::Result<()> {
+        let address = self
+            .x_id_to_address
+            .get(&x_id)
+            .ok_or_else(|| eyre::eyre!("User address not found for x_id"))?
+            .clone();
+        let mut user = self.get_user_by_address(address.clone())?;
+        update(&mut user);
+        self.add_user(address, user)
+    }
 }
 
 impl TeleportDB for InMemoryDB {
@@ -39,7 +63,7 @@ impl TeleportDB for InMemoryDB {
     }
 
     fn add_user(&mut self, address: String, user: User) -> eyre::Result<()> {
-        let file_path = Path::new("shared/users").join(format!("{}.user", address));
+        let file_path = Path::new(USERS_DIR).join(format!("{}.user", address));
         log::info!("Saving user to file: {:?}", file_path.clone());
         let mut file = File::create(file_path)?;
         let contents = serde_json::to_string(&user)?;
@@ -52,7 +76,7 @@ impl TeleportDB for InMemoryDB {
     }
 
     fn get_user_by_address(&self, address: String) -> eyre::Result<User> {
-        let file_path = Path::new("shared/users").join(format!("{}.user", address));
+        let file_path = Path::new(USERS_DIR).join(format!("{}.user", address));
         let contents = read_to_string(file_path)?;
         let user: User = serde_json::from_str(&contents)?;
         Ok(user.clone())
@@ -67,17 +91,34 @@ impl TeleportDB for InMemoryDB {
         Ok(user.clone())
     }
 
+    fn set_reauth_required(&mut self, x_id: String, reauth_required: bool) -> eyre::Result<()> {
+        self.update_user_by_x_id(x_id, |user| user.reauth_required = reauth_required)
+    }
+
+    fn set_oauth2_tokens(&mut self, x_id: String, tokens: OAuth2Tokens) -> eyre::Result<()> {
+        self.update_user_by_x_id(x_id, |user| user.oauth2_tokens = Some(tokens))
+    }
+
     fn serialize(&self) -> eyre::Result<Vec<u8>> {
         let serialized = bincode::serialize(&self)?;
         Ok(serialized)
     }
 
+    fn is_durable(&self) -> bool {
+        // Only users are saved to files.
+        false
+    }
+
     fn add_pending_nft(&mut self, tx_hash: String, pending_nft: PendingNFT) -> eyre::Result<()> {
         self.pending_nfts.insert(tx_hash, pending_nft);
         Ok(())
     }
 
     fn promote_pending_nft(&mut self, tx_hash: String, token_id: String) -> eyre::Result<String> {
+        // A replayed NewTokenData event, possibly carrying the hash of a replacement transaction.
+        if let Some((nft_id, _)) = self.nfts.iter().find(|(_, nft)| nft.token_id == token_id) {
+            return Ok(nft_id.clone());
+        }
         let pending_nft = self
             .pending_nfts
             .remove(&tx_hash)
@@ -89,6 +130,27 @@ impl TeleportDB for InMemoryDB {
         Ok(nft_id_clone)
     }
 
+    fn rename_pending_nft(&mut self, tx_hash: String, new_tx_hash: String) -> eyre::Result<()> {
+        let pending_nft = self
+            .pending_nfts
+            .remove(&tx_hash)
+            .ok_or_else(|| eyre::eyre!("Pending NFT not found"))?;
+        self.pending_nfts.insert(new_tx_hash, pending_nft);
+        Ok(())
+    }
+
+    fn demote_nft(&mut self, token_id: String, tx_hash: String) -> eyre::Result<()> {
+        let (nft_id, _) = self
+            .nfts
+            .iter()
+            .find(|(_, nft)| nft.token_id == token_id)
+            .ok_or_else(|| eyre::eyre!("NFT not found"))?;
+        let nft_id = nft_id.clone();
+        let nft = self.nfts.remove(&nft_id).ok_or_else(|| eyre::eyre!("NFT not found"))?;
+        self.pending_nfts.insert(tx_hash, PendingNFT { address: nft.address, nft_id });
+        Ok(())
+    }
+
     fn get_nft(&self, nft_id: String) -> eyre::Result<NFT> {
         let nft = self.nfts.get(&nft_id).ok_or_else(|| eyre::eyre!("NFT not found"))?;
         Ok(nft.clone())
@@ -104,6 +166,41 @@ impl TeleportDB for InMemoryDB {
         Ok(tweet_id.clone())
     }
 
+    fn add_batch_tweet(&mut self, group: String, tweet_id: String) -> eyre::Result<()> {
+        self.batch_tweets.insert(group, tweet_id);
+        Ok(())
+    }
+
+    fn get_batch_tweet(&self, group: String) -> eyre::Result<String> {
+        let tweet_id =
+            self.batch_tweets.get(&group).ok_or_else(|| eyre::eyre!("Batch tweet not found"))?;
+        Ok(tweet_id.clone())
+    }
+
+    fn get_redeem_nonce(&self, address: String) -> eyre::Result<u64> {
+        Ok(self.redeem_nonces.get(&address).copied().unwrap_or_default())
+    }
+
+    fn use_redeem_nonce(&mut self, address: String, nonce: u64) -> eyre::Result<()> {
+        let next_nonce = self.redeem_nonces.entry(address).or_default();
+        if nonce != *next_nonce {
+            eyre::bail!("Expected redeem nonce {}, got {}", next_nonce, nonce);
+        }
+        *next_nonce += 1;
+        Ok(())
+    }
+
+    fn set_redemption(&mut self, redemption: Redemption) -> eyre::Result<()> {
+        self.redemptions.insert(redemption.token_id.clone(), redemption);
+        Ok(())
+    }
+
+    fn get_redemption(&self, token_id: String) -> eyre::Result<Redemption> {
+        let redemption =
+            self.redemptions.get(&token_id).ok_or_else(|| eyre::eyre!("Redemption not found"))?;
+        Ok(redemption.clone())
+    }
+
     fn add_session(&mut self, session: Session) -> eyre::Result<String> {
         let session_id: i128 = rand::random();
         self.sessions.insert(session_id.to_string(), session);
@@ -119,7 +216,7 @@ impl TeleportDB for InMemoryDB {
 
 #[cfg(test)]
 mod tests {
-    use crate::db::AccessTokens;
+    use crate::db::{AccessTokens, RedemptionStatus};
 
     use super::*;
 
@@ -132,6 +229,7 @@ mod tests {
             x_id: None,
             access_tokens: Some(access_tokens.clone()),
             oauth_tokens: access_tokens.clone(),
+            ..Default::default()
         };
         db.add_user("2".to_string(), user.clone()).expect("Failed to add user tokens");
         let user = db.get_user_by_address("2".to_string())?;
@@ -148,6 +246,7 @@ mod tests {
             x_id: None,
             access_tokens: Some(access_tokens.clone()),
             oauth_tokens: access_tokens.clone(),
+            ..Default::default()
         };
         db.add_user("2".to_string(), user.clone()).expect("Failed to add user tokens");
         user.x_id = Some("1".to_string());
@@ -156,4 +255,83 @@ mod tests {
         assert_eq!(user, fetched_user);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn db_test_reauth_required() -> eyre::Result<()> {
+        let mut db = InMemoryDB::new();
+        let user = User { x_id: Some("3".to_string()), ..Default::default() };
+        db.add_user("3".to_string(), user).expect("Failed to add user tokens");
+        db.set_reauth_required("3".to_string(), true)?;
+        assert!(db.get_user_by_address("3".to_string())?.reauth_required);
+        db.set_reauth_required("3".to_string(), false)?;
+        assert!(!db.get_user_by_x_id("3".to_string())?.reauth_required);
+        assert!(db.set_reauth_required("4".to_string(), true).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_test_redemption_status() -> eyre::Result<()> {
+        let mut db = InMemoryDB::new();
+        let mut redemption = Redemption::pending("7".to_string(), None, "gm".to_string());
+        db.set_redemption(redemption.clone())?;
+        assert_eq!(db.get_redemption("7".to_string())?.status, RedemptionStatus::Pending);
+        redemption.status = RedemptionStatus::Rejected;
+        db.set_redemption(redemption.clone())?;
+        assert_eq!(db.get_redemption("7".to_string())?, redemption);
+        assert!(db.get_redemption("8".to_string()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_test_rename_pending_nft() -> eyre::Result<()> {
+        let mut db = InMemoryDB::new();
+        let pending_nft = PendingNFT { address: "0xabc".to_string(), nft_id: "n1".to_string() };
+        db.add_pending_nft("0x01".to_string(), pending_nft)?;
+        db.rename_pending_nft("0x01".to_string(), "0x02".to_string())?;
+        assert!(db.promote_pending_nft("0x01".to_string(), "5".to_string()).is_err());
+        assert_eq!(db.promote_pending_nft("0x02".to_string(), "5".to_string())?, "n1");
+        assert_eq!(db.get_nft("n1".to_string())?.token_id, "5");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_test_promote_pending_nft_twice() -> eyre::Result<()> {
+        let mut db = InMemoryDB::new();
+        let pending_nft = PendingNFT { address: "0xabc".to_string(), nft_id: "n1".to_string() };
+        db.add_pending_nft("0x01".to_string(), pending_nft)?;
+        assert_eq!(db.promote_pending_nft("0x01".to_string(), "5".to_string())?, "n1");
+        // The NewTokenData event of a replaced transaction carries the mined hash.
+        assert_eq!(db.promote_pending_nft("0x02".to_string(), "5".to_string())?, "n1");
+        assert!(db.promote_pending_nft("0x03".to_string(), "6".to_string()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_test_demote_nft() -> eyre::Result<()> {
+        let mut db = InMemoryDB::new();
+        let pending_nft = PendingNFT { address: "0xabc".to_string(), nft_id: "n1".to_string() };
+        db.add_pending_nft("0x01".to_string(), pending_nft)?;
+        db.promote_pending_nft("0x01".to_string(), "5".to_string())?;
+        db.demote_nft("5".to_string(), "0x01".to_string())?;
+        assert!(db.get_nft("n1".to_string()).is_err());
+        assert!(db.demote_nft("5".to_string(), "0x01".to_string()).is_err());
+        // The mint may land in another block with another token id.
+        assert_eq!(db.promote_pending_nft("0x01".to_string(), "6".to_string())?, "n1");
+        assert_eq!(db.get_nft("n1".to_string())?.token_id, "6");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_test_redeem_nonce() -> eyre::Result<()> {
+        let mut db = InMemoryDB::new();
+        assert_eq!(db.get_redeem_nonce("0xabc".to_string())?, 0);
+        db.use_redeem_nonce("0xabc".to_string(), 0)?;
+        // A nonce is only good once.
+        assert!(db.use_redeem_nonce("0xabc".to_string(), 0).is_err());
+        assert!(db.use_redeem_nonce("0xabc".to_string(), 2).is_err());
+        db.use_redeem_nonce("0xabc".to_string(), 1)?;
+        assert_eq!(db.get_redeem_nonce("0xabc".to_string())?, 2);
+        assert_eq!(db.get_redeem_nonce("0xdef".to_string())?, 0);
+        Ok(())
+    }
 }
@@ -1,10 +1,15 @@
+use std::path::Path;
+
 use rusqlite_from_row::FromRow;
 use serde::{Deserialize, Serialize};
 
-use crate::twitter::auth::TwitterTokenPair;
+use crate::{
+    policy::PolicyVerdict,
+    twitter::{auth::TwitterTokenPair, oauth2::OAuth2Tokens},
+};
 pub mod client_db;
 pub mod in_memory;
-// pub mod sqlite;
+pub mod sqlite;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
 pub struct AccessTokens {
@@ -29,6 +34,26 @@ pub struct User {
     pub x_id: Option<String>,
     pub access_tokens: Option<AccessTokens>,
     pub oauth_tokens: AccessTokens,
+    /// Set when X rejects the access tokens, until the user logs in again through `/new`.
+    #[serde(default)]
+    pub reauth_required: bool,
+    /// Tokens from the OAuth 2.0 login, used instead of `access_tokens` when present.
+    #[serde(default)]
+    pub oauth2_tokens: Option<OAuth2Tokens>,
+    /// An OAuth 2.0 login that has been started but not yet completed.
+    #[serde(default)]
+    pub oauth2_login: Option<OAuth2Login>,
+    /// Nonce of the next `AccountLink` signed for this user.
+    #[serde(default)]
+    pub account_link_nonce: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OAuth2Login {
+    /// Sent as part of the OAuth `state` to tie the callback to this login.
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub frontend_url: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow, PartialEq, Eq)]
@@ -49,6 +74,95 @@ pub struct Session {
     pub x_id: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedemptionStatus {
+    Pending,
+    Posted,
+    Rejected,
+    Failed,
+}
+
+impl RedemptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedemptionStatus::Pending => "pending",
+            RedemptionStatus::Posted => "posted",
+            RedemptionStatus::Rejected => "rejected",
+            RedemptionStatus::Failed => "failed",
+        }
+    }
+}
+
+/// What redeeming a token does on X, matching the contract's `NFT.TokenType` enum.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    #[default]
+    Tweet,
+    Like,
+    Retweet,
+}
+
+impl TokenType {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            TokenType::Tweet => 0,
+            TokenType::Like => 1,
+            TokenType::Retweet => 2,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> eyre::Result<Self> {
+        match value {
+            0 => Ok(TokenType::Tweet),
+            1 => Ok(TokenType::Like),
+            2 => Ok(TokenType::Retweet),
+            _ => eyre::bail!("Unknown token type {}", value),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenType::Tweet => "tweet",
+            TokenType::Like => "like",
+            TokenType::Retweet => "retweet",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Redemption {
+    pub token_id: String,
+    #[serde(default)]
+    pub token_type: TokenType,
+    pub status: RedemptionStatus,
+    pub tx_hash: Option<String>,
+    pub content: String,
+    pub verdict: Option<PolicyVerdict>,
+    pub tweet_id: Option<String>,
+    pub error: Option<String>,
+    /// Hex sha256 of each media file, as checked against the policy.
+    #[serde(default)]
+    pub media_hashes: Vec<String>,
+}
+
+impl Redemption {
+    pub fn pending(token_id: String, tx_hash: Option<String>, content: String) -> Self {
+        Self {
+            token_id,
+            token_type: TokenType::Tweet,
+            status: RedemptionStatus::Pending,
+            tx_hash,
+            content,
+            verdict: None,
+            tweet_id: None,
+            error: None,
+            media_hashes: vec![],
+        }
+    }
+}
+
 pub trait TeleportDB: Send + Sync + 'static {
     // async fn init(&mut self) -> eyre::Result<()>;
     // async fn open_from_file(file_path: &str) -> eyre::Result<Self>;
@@ -57,12 +171,121 @@ pub trait TeleportDB: Send + Sync + 'static {
     fn add_user(&mut self, address: String, user: User) -> eyre::Result<()>;
     fn get_user_by_address(&self, address: String) -> eyre::Result<User>;
     fn get_user_by_x_id(&self, x_id: String) -> eyre::Result<User>;
+    fn set_reauth_required(&mut self, x_id: String, reauth_required: bool) -> eyre::Result<()>;
+    fn set_oauth2_tokens(&mut self, x_id: String, tokens: OAuth2Tokens) -> eyre::Result<()>;
     fn add_pending_nft(&mut self, tx_hash: String, pending_nft: PendingNFT) -> eyre::Result<()>;
     fn promote_pending_nft(&mut self, tx_hash: String, token_id: String) -> eyre::Result<String>;
+    fn rename_pending_nft(&mut self, tx_hash: String, new_tx_hash: String) -> eyre::Result<()>;
+    /// Undoes [`TeleportDB::promote_pending_nft`], e.g. because a reorg removed the mint.
+    fn demote_nft(&mut self, token_id: String, tx_hash: String) -> eyre::Result<()>;
     fn get_nft(&self, nft_id: String) -> eyre::Result<NFT>;
     fn add_tweet(&mut self, token_id: String, tweet_id: String) -> eyre::Result<()>;
     fn get_tweet(&self, token_id: String) -> eyre::Result<String>;
+    fn add_batch_tweet(&mut self, group: String, tweet_id: String) -> eyre::Result<()>;
+    fn get_batch_tweet(&self, group: String) -> eyre::Result<String>;
+    fn get_redeem_nonce(&self, address: String) -> eyre::Result<u64>;
+    fn use_redeem_nonce(&mut self, address: String, nonce: u64) -> eyre::Result<()>;
+    fn set_redemption(&mut self, redemption: Redemption) -> eyre::Result<()>;
+    fn get_redemption(&self, token_id: String) -> eyre::Result<Redemption>;
     fn add_session(&mut self, session: Session) -> eyre::Result<String>;
     fn get_session(&self, session_id: String) -> eyre::Result<Session>;
     fn serialize(&self) -> eyre::Result<Vec<u8>>;
+    /// Whether everything stored survives a restart of the enclave.
+    fn is_durable(&self) -> bool;
+}
+
+/// Lets the backend be chosen at startup while handlers stay generic over `TeleportDB`.
+impl TeleportDB for Box<dyn TeleportDB> {
+    fn add_oauth(&mut self, token: String, secret: String) -> eyre::Result<()> {
+        (**self).add_oauth(token, secret)
+    }
+    fn get_oauth(&mut self, token: String) -> eyre::Result<String> {
+        (**self).get_oauth(token)
+    }
+    fn add_user(&mut self, address: String, user: User) -> eyre::Result<()> {
+        (**self).add_user(address, user)
+    }
+    fn get_user_by_address(&self, address: String) -> eyre::Result<User> {
+        (**self).get_user_by_address(address)
+    }
+    fn get_user_by_x_id(&self, x_id: String) -> eyre::Result<User> {
+        (**self).get_user_by_x_id(x_id)
+    }
+    fn set_reauth_required(&mut self, x_id: String, reauth_required: bool) -> eyre::Result<()> {
+        (**self).set_reauth_required(x_id, reauth_required)
+    }
+    fn set_oauth2_tokens(&mut self, x_id: String, tokens: OAuth2Tokens) -> eyre::Result<()> {
+        (**self).set_oauth2_tokens(x_id, tokens)
+    }
+    fn add_pending_nft(&mut self, tx_hash: String, pending_nft: PendingNFT) -> eyre::Result<()> {
+        (**self).add_pending_nft(tx_hash, pending_nft)
+    }
+    fn promote_pending_nft(&mut self, tx_hash: String, token_id: String) -> eyre::Result<String> {
+        (**self).promote_pending_nft(tx_hash, token_id)
+    }
+    fn rename_pending_nft(&mut self, tx_hash: String, new_tx_hash: String) -> eyre::Result<()> {
+        (**self).rename_pending_nft(tx_hash, new_tx_hash)
+    }
+    fn demote_nft(&mut self, token_id: String, tx_hash: String) -> eyre::Result<()> {
+        (**self).demote_nft(token_id, tx_hash)
+    }
+    fn get_nft(&self, nft_id: String) -> eyre::Result<NFT> {
+        (**self).get_nft(nft_id)
+    }
+    fn add_tweet(&mut self, token_id: String, tweet_id: String) -> eyre::Result<()> {
+        (**self).add_tweet(token_id, tweet_id)
+    }
+    fn get_tweet(&self, token_id: String) -> eyre::Result<String> {
+        (**self).get_tweet(token_id)
+    }
+    fn add_batch_tweet(&mut self, group: String, tweet_id: String) -> eyre::Result<()> {
+        (**self).add_batch_tweet(group, tweet_id)
+    }
+    fn get_batch_tweet(&self, group: String) -> eyre::Result<String> {
+        (**self).get_batch_tweet(group)
+    }
+    fn get_redeem_nonce(&self, address: String) -> eyre::Result<u64> {
+        (**self).get_redeem_nonce(address)
+    }
+    fn use_redeem_nonce(&mut self, address: String, nonce: u64) -> eyre::Result<()> {
+        (**self).use_redeem_nonce(address, nonce)
+    }
+    fn set_redemption(&mut self, redemption: Redemption) -> eyre::Result<()> {
+        (**self).set_redemption(redemption)
+    }
+    fn get_redemption(&self, token_id: String) -> eyre::Result<Redemption> {
+        (**self).get_redemption(token_id)
+    }
+    fn add_session(&mut self, session: Session) -> eyre::Result<String> {
+        (**self).add_session(session)
+    }
+    fn get_session(&self, session_id: String) -> eyre::Result<Session> {
+        (**self).get_session(session_id)
+    }
+    fn serialize(&self) -> eyre::Result<Vec<u8>> {
+        (**self).serialize()
+    }
+    fn is_durable(&self) -> bool {
+        (**self).is_durable()
+    }
+}
+
+/// Opens the store named by `DB_BACKEND`: `sqlite` (the default) at `sqlite_path`, or `memory`
+/// for the file-per-user `InMemoryDB`. A new SQLite file starts with the users from those files.
+pub fn open_from_env(sqlite_path: &str) -> eyre::Result<Box<dyn TeleportDB>> {
+    let backend = std::env::var("DB_BACKEND").unwrap_or_else(|_| "sqlite".to_string());
+    log::info!("Using {} database backend", backend);
+    match backend.as_str() {
+        "memory" => Ok(Box::new(in_memory::InMemoryDB::new())),
+        "sqlite" => {
+            let created = !Path::new(sqlite_path).exists();
+            let mut db = sqlite::SqliteDB::open(sqlite_path)?;
+            if created && Path::new(in_memory::USERS_DIR).exists() {
+                let imported = db.import_user_files(in_memory::USERS_DIR)?;
+                log::info!("Imported {} users from {}", imported, in_memory::USERS_DIR);
+            }
+            Ok(Box::new(db))
+        }
+        _ => eyre::bail!("Unknown DB_BACKEND {}", backend),
+    }
 }
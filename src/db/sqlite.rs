@@ -0,0 +1,513 @@
+use std::{
+    fs::{read_dir, read_to_string},
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use rusqlite::{params, Connection, DatabaseName, OptionalExtension};
+use rusqlite_from_row::FromRow;
+
+use super::{PendingNFT, Redemption, Session, TeleportDB, User, NFT};
+use crate::twitter::oauth2::OAuth2Tokens;
+
+/// Schema changes in the order they were made. `PRAGMA user_version` counts the ones applied, so
+/// new changes are appended here and existing ones are never edited.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE users (
+        address TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE x_accounts (
+        x_id TEXT PRIMARY KEY,
+        address TEXT NOT NULL REFERENCES users (address)
+    );
+    CREATE TABLE oauths (
+        token TEXT PRIMARY KEY,
+        secret TEXT NOT NULL
+    );
+    CREATE TABLE pending_nfts (
+        tx_hash TEXT PRIMARY KEY,
+        address TEXT NOT NULL,
+        nft_id TEXT NOT NULL
+    );
+    CREATE TABLE nfts (
+        nft_id TEXT PRIMARY KEY,
+        address TEXT NOT NULL,
+        token_id TEXT NOT NULL
+    );
+    CREATE UNIQUE INDEX nfts_token_id ON nfts (token_id);
+    CREATE TABLE tweets (
+        token_id TEXT PRIMARY KEY,
+        tweet_id TEXT NOT NULL
+    );
+    CREATE TABLE batch_tweets (
+        job_group TEXT PRIMARY KEY,
+        tweet_id TEXT NOT NULL
+    );
+    CREATE TABLE redeem_nonces (
+        address TEXT PRIMARY KEY,
+        next_nonce INTEGER NOT NULL
+    );
+    CREATE TABLE redemptions (
+        token_id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE sessions (
+        session_id TEXT PRIMARY KEY,
+        address TEXT NOT NULL,
+        x_id TEXT NOT NULL
+    );
+"];
+
+/// `TeleportDB` kept in a SQLite file, so that users, sessions and NFTs survive a restart.
+pub struct SqliteDB {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteDB {
+    /// Opens the database at `path`, creating it if needed, and applies pending migrations.
+    pub fn open(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> eyre::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> eyre::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // Writes are single statements or transactions, so a panic can't leave one half done.
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Copies the users that `InMemoryDB` saved as files in `dir`, keeping any already stored.
+    pub fn import_user_files(&mut self, dir: impl AsRef<Path>) -> eyre::Result<usize> {
+        let mut imported = 0;
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            let Some(address) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if path.extension().and_then(|ext| ext.to_str()) != Some("user") ||
+                self.get_user_by_address(address.to_string()).is_ok()
+            {
+                continue;
+            }
+            let user: User = serde_json::from_str(&read_to_string(&path)?)?;
+            self.add_user(address.to_string(), user)?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    fn update_user_by_x_id(
+        &mut self,
+        x_id: String,
+        update: impl FnOnce(&mut User),
+    ) -> eyre::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let (address, data): (String, String) = tx
+            .query_row(
+                "SELECT address, data FROM x_accounts JOIN users USING (address) WHERE x_id = ?1",
+                params![x_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("User address not found for x_id"))?;
+        let mut user: User = serde_json::from_str(&data)?;
+        update(&mut user);
+        tx.execute(
+            "UPDATE users SET data = ?1 WHERE address = ?2",
+            params![serde_json::to_string(&user)?, address],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> eyre::Result<()> {
+    let tx = conn.transaction()?;
+    let version: u32 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        eyre::bail!("Database schema version {} is newer than this build", version);
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::info!("Applying database migration {}", i + 1);
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len() as u32)?;
+    tx.commit()?;
+    Ok(())
+}
+
+impl TeleportDB for SqliteDB {
+    fn add_oauth(&mut self, token: String, secret: String) -> eyre::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO oauths (token, secret) VALUES (?1, ?2)",
+            params![token, secret],
+        )?;
+        Ok(())
+    }
+
+    fn get_oauth(&mut self, token: String) -> eyre::Result<String> {
+        let secret = self
+            .conn()
+            .query_row("SELECT secret FROM oauths WHERE token = ?1", params![token], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("OAuth not found"))?;
+        Ok(secret)
+    }
+
+    fn add_user(&mut self, address: String, user: User) -> eyre::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO users (address, data) VALUES (?1, ?2)
+             ON CONFLICT (address) DO UPDATE SET data = excluded.data",
+            params![address, serde_json::to_string(&user)?],
+        )?;
+        if let Some(x_id) = user.x_id {
+            tx.execute(
+                "INSERT OR REPLACE INTO x_accounts (x_id, address) VALUES (?1, ?2)",
+                params![x_id, address],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_user_by_address(&self, address: String) -> eyre::Result<User> {
+        let data: String = self
+            .conn()
+            .query_row("SELECT data FROM users WHERE address = ?1", params![address], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("User not found"))?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn get_user_by_x_id(&self, x_id: String) -> eyre::Result<User> {
+        let data: String = self
+            .conn()
+            .query_row(
+                "SELECT data FROM x_accounts JOIN users USING (address) WHERE x_id = ?1",
+                params![x_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("User address not found for x_id"))?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn set_reauth_required(&mut self, x_id: String, reauth_required: bool) -> eyre::Result<()> {
+        self.update_user_by_x_id(x_id, |user| user.reauth_required = reauth_required)
+    }
+
+    fn set_oauth2_tokens(&mut self, x_id: String, tokens: OAuth2Tokens) -> eyre::Result<()> {
+        self.update_user_by_x_id(x_id, |user| user.oauth2_tokens = Some(tokens))
+    }
+
+    fn add_pending_nft(&mut self, tx_hash: String, pending_nft: PendingNFT) -> eyre::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO pending_nfts (tx_hash, address, nft_id) VALUES (?1, ?2, ?3)",
+            params![tx_hash, pending_nft.address, pending_nft.nft_id],
+        )?;
+        Ok(())
+    }
+
+    fn promote_pending_nft(&mut self, tx_hash: String, token_id: String) -> eyre::Result<String> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        // A replayed NewTokenData event, possibly carrying the hash of a replacement transaction.
+        let promoted: Option<String> = tx
+            .query_row("SELECT nft_id FROM nfts WHERE token_id = ?1", params![token_id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        if let Some(nft_id) = promoted {
+            return Ok(nft_id);
+        }
+        let pending_nft = tx
+            .query_row(
+                "SELECT address, nft_id FROM pending_nfts WHERE tx_hash = ?1",
+                params![tx_hash],
+                PendingNFT::try_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("Pending NFT not found"))?;
+        tx.execute("DELETE FROM pending_nfts WHERE tx_hash = ?1", params![tx_hash])?;
+        tx.execute(
+            "INSERT INTO nfts (nft_id, address, token_id) VALUES (?1, ?2, ?3)",
+            params![pending_nft.nft_id, pending_nft.address, token_id],
+        )?;
+        tx.commit()?;
+        Ok(pending_nft.nft_id)
+    }
+
+    fn rename_pending_nft(&mut self, tx_hash: String, new_tx_hash: String) -> eyre::Result<()> {
+        let renamed = self.conn().execute(
+            "UPDATE OR REPLACE pending_nfts SET tx_hash = ?2 WHERE tx_hash = ?1",
+            params![tx_hash, new_tx_hash],
+        )?;
+        if renamed == 0 {
+            eyre::bail!("Pending NFT not found");
+        }
+        Ok(())
+    }
+
+    fn demote_nft(&mut self, token_id: String, tx_hash: String) -> eyre::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let pending_nft = tx
+            .query_row(
+                "SELECT address, nft_id FROM nfts WHERE token_id = ?1",
+                params![token_id],
+                PendingNFT::try_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("NFT not found"))?;
+        tx.execute("DELETE FROM nfts WHERE token_id = ?1", params![token_id])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO pending_nfts (tx_hash, address, nft_id) VALUES (?1, ?2, ?3)",
+            params![tx_hash, pending_nft.address, pending_nft.nft_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_nft(&self, nft_id: String) -> eyre::Result<NFT> {
+        let nft = self
+            .conn()
+            .query_row(
+                "SELECT address, token_id FROM nfts WHERE nft_id = ?1",
+                params![nft_id],
+                NFT::try_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("NFT not found"))?;
+        Ok(nft)
+    }
+
+    fn add_tweet(&mut self, token_id: String, tweet_id: String) -> eyre::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO tweets (token_id, tweet_id) VALUES (?1, ?2)",
+            params![token_id, tweet_id],
+        )?;
+        Ok(())
+    }
+
+    fn get_tweet(&self, token_id: String) -> eyre::Result<String> {
+        let tweet_id = self
+            .conn()
+            .query_row(
+                "SELECT tweet_id FROM tweets WHERE token_id = ?1",
+                params![token_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("Tweet not found"))?;
+        Ok(tweet_id)
+    }
+
+    fn add_batch_tweet(&mut self, group: String, tweet_id: String) -> eyre::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO batch_tweets (job_group, tweet_id) VALUES (?1, ?2)",
+            params![group, tweet_id],
+        )?;
+        Ok(())
+    }
+
+    fn get_batch_tweet(&self, group: String) -> eyre::Result<String> {
+        let tweet_id = self
+            .conn()
+            .query_row(
+                "SELECT tweet_id FROM batch_tweets WHERE job_group = ?1",
+                params![group],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("Batch tweet not found"))?;
+        Ok(tweet_id)
+    }
+
+    fn get_redeem_nonce(&self, address: String) -> eyre::Result<u64> {
+        let next_nonce: Option<i64> = self
+            .conn()
+            .query_row(
+                "SELECT next_nonce FROM redeem_nonces WHERE address = ?1",
+                params![address],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(u64::try_from(next_nonce.unwrap_or_default())?)
+    }
+
+    fn use_redeem_nonce(&mut self, address: String, nonce: u64) -> eyre::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let next_nonce: i64 = tx
+            .query_row(
+                "SELECT next_nonce FROM redeem_nonces WHERE address = ?1",
+                params![address],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or_default();
+        if i64::try_from(nonce)? != next_nonce {
+            eyre::bail!("Expected redeem nonce {}, got {}", next_nonce, nonce);
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO redeem_nonces (address, next_nonce) VALUES (?1, ?2)",
+            params![address, next_nonce + 1],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_redemption(&mut self, redemption: Redemption) -> eyre::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO redemptions (token_id, data) VALUES (?1, ?2)",
+            params![redemption.token_id, serde_json::to_string(&redemption)?],
+        )?;
+        Ok(())
+    }
+
+    fn get_redemption(&self, token_id: String) -> eyre::Result<Redemption> {
+        let data: String = self
+            .conn()
+            .query_row(
+                "SELECT data FROM redemptions WHERE token_id = ?1",
+                params![token_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("Redemption not found"))?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn add_session(&mut self, session: Session) -> eyre::Result<String> {
+        let session_id: i128 = rand::random();
+        self.conn().execute(
+            "INSERT INTO sessions (session_id, address, x_id) VALUES (?1, ?2, ?3)",
+            params![session_id.to_string(), session.address, session.x_id],
+        )?;
+        Ok(session_id.to_string())
+    }
+
+    fn get_session(&self, session_id: String) -> eyre::Result<Session> {
+        let session = self
+            .conn()
+            .query_row(
+                "SELECT address, x_id FROM sessions WHERE session_id = ?1",
+                params![session_id],
+                Session::try_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| eyre::eyre!("Session not found"))?;
+        Ok(session)
+    }
+
+    fn serialize(&self) -> eyre::Result<Vec<u8>> {
+        Ok(self.conn().serialize(DatabaseName::Main)?.to_vec())
+    }
+
+    fn is_durable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{AccessTokens, RedemptionStatus};
+
+    use super::*;
+
+    #[test]
+    fn sqlite_test_users() -> eyre::Result<()> {
+        let mut db = SqliteDB::open_in_memory()?;
+        let access_tokens =
+            AccessTokens { token: "access token".to_string(), secret: "access secret".to_string() };
+        let mut user = User { oauth_tokens: access_tokens.clone(), ..Default::default() };
+        db.add_user("2".to_string(), user.clone())?;
+        assert_eq!(db.get_user_by_address("2".to_string())?, user);
+        assert!(db.get_user_by_x_id("1".to_string()).is_err());
+
+        user.x_id = Some("1".to_string());
+        user.access_tokens = Some(access_tokens);
+        db.add_user("2".to_string(), user.clone())?;
+        assert_eq!(db.get_user_by_x_id("1".to_string())?, user);
+
+        db.set_reauth_required("1".to_string(), true)?;
+        assert!(db.get_user_by_address("2".to_string())?.reauth_required);
+        assert!(db.set_reauth_required("3".to_string(), true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_test_nfts() -> eyre::Result<()> {
+        let mut db = SqliteDB::open_in_memory()?;
+        let pending_nft = PendingNFT { address: "0xabc".to_string(), nft_id: "n1".to_string() };
+        db.add_pending_nft("0x01".to_string(), pending_nft)?;
+        db.rename_pending_nft("0x01".to_string(), "0x02".to_string())?;
+        assert!(db.rename_pending_nft("0x01".to_string(), "0x03".to_string()).is_err());
+        assert_eq!(db.promote_pending_nft("0x02".to_string(), "5".to_string())?, "n1");
+        assert_eq!(db.promote_pending_nft("0x04".to_string(), "5".to_string())?, "n1");
+        assert!(db.promote_pending_nft("0x02".to_string(), "6".to_string()).is_err());
+        assert_eq!(
+            db.get_nft("n1".to_string())?,
+            NFT { address: "0xabc".to_string(), token_id: "5".to_string() }
+        );
+
+        db.demote_nft("5".to_string(), "0x02".to_string())?;
+        assert!(db.get_nft("n1".to_string()).is_err());
+        assert!(db.demote_nft("5".to_string(), "0x02".to_string()).is_err());
+        assert_eq!(db.promote_pending_nft("0x02".to_string(), "6".to_string())?, "n1");
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_test_redemptions() -> eyre::Result<()> {
+        let mut db = SqliteDB::open_in_memory()?;
+        let mut redemption = Redemption::pending("7".to_string(), None, "gm".to_string());
+        db.set_redemption(redemption.clone())?;
+        redemption.status = RedemptionStatus::Posted;
+        db.set_redemption(redemption.clone())?;
+        assert_eq!(db.get_redemption("7".to_string())?, redemption);
+        db.add_tweet("7".to_string(), "t1".to_string())?;
+        assert_eq!(db.get_tweet("7".to_string())?, "t1");
+
+        db.use_redeem_nonce("0xabc".to_string(), 0)?;
+        assert!(db.use_redeem_nonce("0xabc".to_string(), 0).is_err());
+        assert_eq!(db.get_redeem_nonce("0xabc".to_string())?, 1);
+
+        let session = Session { address: "0xabc".to_string(), x_id: "1".to_string() };
+        let session_id = db.add_session(session.clone())?;
+        assert_eq!(db.get_session(session_id)?, session);
+        Ok(())
+    }
+
+    #[test]
+    fn sqlite_test_reopen() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join(format!("teleport-{}.db", rand::random::<u64>()));
+        let mut db = SqliteDB::open(&path)?;
+        db.add_batch_tweet("0x01:0xabc".to_string(), "t1".to_string())?;
+        drop(db);
+
+        // Migrations that already ran are skipped and the data is kept.
+        let db = SqliteDB::open(&path)?;
+        assert_eq!(db.get_batch_tweet("0x01:0xabc".to_string())?, "t1");
+        drop(db);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
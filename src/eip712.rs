@@ -0,0 +1,189 @@
+//! EIP-712 typed data exchanged between users and the enclave.
+
+use std::str::FromStr;
+
+use alloy::{
+    primitives::{keccak256, Address, Signature, U256},
+    sol,
+    sol_types::{eip712_domain, Eip712Domain, SolStruct},
+};
+
+sol! {
+    /// Lets the enclave redeem `tokenId` with the content whose keccak256 hash is `contentHash`.
+    /// Signed by the token's current owner.
+    #[derive(Debug, PartialEq, Eq)]
+    struct RedeemAuthorization {
+        uint256 tokenId;
+        bytes32 contentHash;
+        uint8 tokenType;
+        uint256 nonce;
+        uint256 deadline;
+    }
+
+    /// Lets the enclave redeem every token in `tokenIds` with one tweet, the content whose
+    /// keccak256 hash is `contentHash`. Signed by the owner of the tokens.
+    #[derive(Debug, PartialEq, Eq)]
+    struct RedeemBatchAuthorization {
+        uint256[] tokenIds;
+        bytes32 contentHash;
+        uint256 nonce;
+        uint256 deadline;
+    }
+
+    /// Links the wallet `account` to the X account `xId`. Signed by the enclave once the user has
+    /// logged in with X.
+    #[derive(Debug, PartialEq, Eq)]
+    struct AccountLink {
+        address account;
+        uint256 xId;
+        uint256 nonce;
+        uint256 expiry;
+    }
+}
+
+/// The Teleport domain on the chain of the NFT contract, salted with the enclave's address so
+/// that signatures for one enclave are not valid for another.
+pub fn teleport_domain(chain_id: u64, nft_address: Address, enclave: Address) -> Eip712Domain {
+    eip712_domain! {
+        name: "Teleport",
+        version: "1",
+        chain_id: chain_id,
+        verifying_contract: nft_address,
+        salt: enclave.into_word(),
+    }
+}
+
+impl RedeemAuthorization {
+    pub fn new(token_id: U256, content: &str, token_type: u8, nonce: u64, deadline: u64) -> Self {
+        Self {
+            tokenId: token_id,
+            contentHash: keccak256(content),
+            tokenType: token_type,
+            nonce: U256::from(nonce),
+            deadline: U256::from(deadline),
+        }
+    }
+
+    /// Recovers the address that signed the authorization, failing if it has expired.
+    pub fn recover_signer(&self, domain: &Eip712Domain, signature: &str) -> eyre::Result<Address> {
+        recover_redeem_signer(self, self.deadline, domain, signature)
+    }
+}
+
+impl RedeemBatchAuthorization {
+    pub fn new(token_ids: Vec<U256>, content: &str, nonce: u64, deadline: u64) -> Self {
+        Self {
+            tokenIds: token_ids,
+            contentHash: keccak256(content),
+            nonce: U256::from(nonce),
+            deadline: U256::from(deadline),
+        }
+    }
+
+    /// Recovers the address that signed the authorization, failing if it has expired.
+    pub fn recover_signer(&self, domain: &Eip712Domain, signature: &str) -> eyre::Result<Address> {
+        recover_redeem_signer(self, self.deadline, domain, signature)
+    }
+}
+
+fn recover_redeem_signer(
+    authorization: &impl SolStruct,
+    deadline: U256,
+    domain: &Eip712Domain,
+    signature: &str,
+) -> eyre::Result<Address> {
+    if deadline < U256::from(chrono::Utc::now().timestamp()) {
+        eyre::bail!("Redeem authorization expired");
+    }
+    let signature = Signature::from_str(signature)?;
+    Ok(signature.recover_address_from_prehash(&authorization.eip712_signing_hash(domain))?)
+}
+
+impl AccountLink {
+    pub fn new(account: Address, x_id: &str, nonce: u64, expiry: u64) -> eyre::Result<Self> {
+        Ok(Self {
+            account,
+            xId: U256::from_str(x_id)?,
+            nonce: U256::from(nonce),
+            expiry: U256::from(expiry),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::signers::{local::PrivateKeySigner, SignerSync};
+
+    use super::*;
+
+    #[test]
+    fn redeem_authorization_test() -> eyre::Result<()> {
+        let owner = PrivateKeySigner::from_slice(&[0x42; 32])?;
+        let domain = teleport_domain(8453, Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        let deadline = chrono::Utc::now().timestamp() as u64 + 600;
+        let authorization = RedeemAuthorization::new(U256::from(7), "gm", 0, 3, deadline);
+        let signature = owner.sign_hash_sync(&authorization.eip712_signing_hash(&domain))?;
+        let signature = alloy::hex::encode_prefixed(signature.as_bytes());
+
+        assert_eq!(authorization.recover_signer(&domain, &signature)?, owner.address());
+
+        // Any other content, nonce or chain recovers some other address.
+        let other_content = RedeemAuthorization::new(U256::from(7), "gn", 0, 3, deadline);
+        assert_ne!(other_content.recover_signer(&domain, &signature)?, owner.address());
+        let other_nonce = RedeemAuthorization::new(U256::from(7), "gm", 0, 4, deadline);
+        assert_ne!(other_nonce.recover_signer(&domain, &signature)?, owner.address());
+        let other_chain =
+            teleport_domain(84532, Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        assert_ne!(authorization.recover_signer(&other_chain, &signature)?, owner.address());
+        let other_enclave =
+            teleport_domain(8453, Address::repeat_byte(0x11), Address::repeat_byte(0x33));
+        assert_ne!(authorization.recover_signer(&other_enclave, &signature)?, owner.address());
+
+        let expired = RedeemAuthorization::new(U256::from(7), "gm", 0, 3, 1);
+        assert!(expired.recover_signer(&domain, &signature).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn redeem_batch_authorization_test() -> eyre::Result<()> {
+        let owner = PrivateKeySigner::from_slice(&[0x42; 32])?;
+        let domain = teleport_domain(8453, Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        let deadline = chrono::Utc::now().timestamp() as u64 + 600;
+        let token_ids = vec![U256::from(7), U256::from(8)];
+        let authorization = RedeemBatchAuthorization::new(token_ids.clone(), "gm", 3, deadline);
+        let signature = owner.sign_hash_sync(&authorization.eip712_signing_hash(&domain))?;
+        let signature = alloy::hex::encode_prefixed(signature.as_bytes());
+
+        assert_eq!(authorization.recover_signer(&domain, &signature)?, owner.address());
+
+        // Any other set of tokens, content or nonce recovers some other address.
+        let other_tokens = RedeemBatchAuthorization::new(vec![U256::from(7)], "gm", 3, deadline);
+        assert_ne!(other_tokens.recover_signer(&domain, &signature)?, owner.address());
+        let other_content = RedeemBatchAuthorization::new(token_ids.clone(), "gn", 3, deadline);
+        assert_ne!(other_content.recover_signer(&domain, &signature)?, owner.address());
+        let other_nonce = RedeemBatchAuthorization::new(token_ids.clone(), "gm", 4, deadline);
+        assert_ne!(other_nonce.recover_signer(&domain, &signature)?, owner.address());
+
+        let expired = RedeemBatchAuthorization::new(token_ids, "gm", 3, 1);
+        assert!(expired.recover_signer(&domain, &signature).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn account_link_test() -> eyre::Result<()> {
+        let enclave = PrivateKeySigner::from_slice(&[0x42; 32])?;
+        let domain = teleport_domain(8453, Address::repeat_byte(0x11), enclave.address());
+        let link = AccountLink::new(Address::repeat_byte(0x44), "1000", 2, 1_700_000_000)?;
+        assert_eq!(link.xId, U256::from(1000));
+
+        let signature = enclave.sign_hash_sync(&link.eip712_signing_hash(&domain))?;
+        let recovered =
+            signature.recover_address_from_prehash(&link.eip712_signing_hash(&domain))?;
+        assert_eq!(recovered, enclave.address());
+        let next = AccountLink::new(Address::repeat_byte(0x44), "1000", 3, 1_700_000_000)?;
+        assert_ne!(link.eip712_signing_hash(&domain), next.eip712_signing_hash(&domain));
+
+        assert!(AccountLink::new(Address::ZERO, "not a number", 0, 0).is_err());
+        Ok(())
+    }
+}
@@ -1,35 +1,63 @@
 use alloy::{
-    primitives::Address,
+    primitives::{keccak256, Address, U256},
     signers::{k256::ecdsa::SigningKey, local::LocalSigner},
+    sol_types::{Eip712Domain, SolStruct},
 };
 use http::HeaderMap;
-use std::{str::FromStr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
 
 use axum::{
     extract::{Query, State},
     http::StatusCode,
     response::{IntoResponse, Redirect},
-    Json,
+    routing::{get, post},
+    Json, Router,
 };
+use futures::{stream, StreamExt, TryStreamExt};
 use rustls::ClientConfig;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, oneshot, Mutex};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
 use tokio_postgres_rustls::MakeRustlsConnect;
+use tower_http::cors::CorsLayer;
 
 use crate::{
-    actions::nft::{get_token_id, NFTAction},
-    db::{in_memory::InMemoryDB, AccessTokens, PendingNFT, Session, TeleportDB},
-    oai,
+    actions::{
+        jobs::{Job, JobQueue},
+        nft::{
+            check_redeem_content, fetch_media, get_owner_of, get_token_id, get_tweet_context,
+            mint_nft, redeem_batch_nft, redeem_nft, track_mint, track_redeem, user_client,
+            wait_for_mint,
+        },
+        subscriber::{SubscriberStatus, SubscriberStatusHandle},
+        tx_manager::{TxError, TxManager},
+    },
+    content::RedeemContent,
+    db::{
+        client_db::ClientDB, AccessTokens, OAuth2Login, PendingNFT, Redemption, Session,
+        TeleportDB, TokenType, User,
+    },
+    eip712::{AccountLink, RedeemAuthorization, RedeemBatchAuthorization},
+    policy::{PolicyChecker, PolicyMedia, PolicyRequest, PolicyVerdict, TweetContext},
     templates::{HtmlTemplate, PolicyTemplate},
-    twitter::builder::TwitterBuilder,
+    twitter::{builder::TwitterBuilder, info::UserInfo},
 };
 
 use alloy::signers::Signer;
 
 use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
-use eyre::OptionExt;
 
 pub const SESSION_ID_COOKIE_NAME: &str = "teleport_session_id";
+/// Most tokens one `/redeemBatch` call may redeem.
+const MAX_BATCH_SIZE: usize = 100;
+/// How many of a `/redeemBatch` call's token reads are in flight at once.
+const BATCH_RPC_CONCURRENCY: usize = 16;
+/// How long an `AccountLink` signed at login stays valid, unless `ACCOUNT_LINK_TTL_SECS` is set.
+const DEFAULT_ACCOUNT_LINK_TTL_SECS: u64 = 60 * 60;
 
 fn default_str() -> String {
     "none".to_string()
@@ -49,10 +77,19 @@ pub struct CallbackQuery {
     frontend_url: String,
 }
 
+#[derive(Deserialize)]
+pub struct OAuth2CallbackQuery {
+    code: String,
+    state: String,
+}
+
 #[derive(Deserialize)]
 pub struct MintQuery {
     address: String,
     policy: String,
+    /// Wait for the mint to be mined and return the token id instead of just the tx hash.
+    #[serde(default)]
+    wait: bool,
 }
 
 #[derive(Deserialize)]
@@ -60,6 +97,33 @@ pub struct TweetIdQuery {
     token_id: String,
 }
 
+#[derive(Deserialize)]
+pub struct RedemptionStatusQuery {
+    token_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct RedriveQuery {
+    id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RedriveResponse {
+    redriven: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ReauthStatusQuery {
+    address: String,
+}
+
+#[derive(Serialize)]
+pub struct ReauthStatusResponse {
+    reauth_required: bool,
+    /// Tokens whose redemption is waiting for the user to log in again.
+    held_token_ids: Vec<String>,
+}
+
 #[derive(Serialize)]
 pub struct TweetIdResponse {
     tweet_id: String,
@@ -73,7 +137,29 @@ pub struct AttestationResponse {
 #[derive(Deserialize)]
 pub struct RedeemQuery {
     nft_id: String,
+    /// The tweet to post (see [`RedeemContent`]), or the id of the tweet to like or retweet.
     content: String,
+    #[serde(default)]
+    token_type: TokenType,
+    /// Skip the pre-flight policy check and send the redeem right away. The content is still
+    /// checked once the redeem is on-chain, but a rejection then burns the token for nothing.
+    #[serde(default)]
+    force: bool,
+    /// Unix time after which `signature` is void.
+    deadline: u64,
+    nonce: u64,
+    /// EIP-712 signature of a [`RedeemAuthorization`] for this request by the token's owner.
+    signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct RedeemNonceQuery {
+    address: String,
+}
+
+#[derive(Serialize)]
+pub struct RedeemNonceResponse {
+    pub nonce: u64,
 }
 
 #[derive(Serialize)]
@@ -81,6 +167,56 @@ pub struct TxHashResponse {
     pub hash: String,
 }
 
+#[derive(Deserialize)]
+pub struct RedeemBatchQuery {
+    /// The logged in user, who must own every token.
+    address: String,
+    nft_ids: Vec<String>,
+    /// The tweet to post (see [`RedeemContent`]), once per account the tokens post as.
+    content: String,
+    /// Skip the pre-flight policy check, as for `/redeem`.
+    #[serde(default)]
+    force: bool,
+    /// Unix time after which `signature` is void.
+    deadline: u64,
+    nonce: u64,
+    /// EIP-712 signature of a [`RedeemBatchAuthorization`] for this request by the tokens' owner.
+    signature: String,
+}
+
+#[derive(Serialize)]
+pub struct RedeemBatchResponse {
+    /// Unset when the pre-flight check rejected the content for any token and nothing was sent.
+    pub hash: Option<String>,
+    /// Pre-flight verdicts by token id, empty for forced redeems.
+    pub verdicts: BTreeMap<String, SignedVerdict>,
+}
+
+#[derive(Serialize)]
+pub struct RedeemResponse {
+    /// Unset when the pre-flight check rejected the content and nothing was sent.
+    pub hash: Option<String>,
+    /// Unset for forced redeems and for likes and retweets.
+    pub verdict: Option<SignedVerdict>,
+}
+
+/// A policy verdict signed by the enclave key.
+#[derive(Serialize)]
+pub struct SignedVerdict {
+    pub safe: bool,
+    pub verdict: PolicyVerdict,
+    /// `token_id={}&content_hash={}&safe={}`, with the keccak256 hash of the redeem content.
+    pub message: String,
+    /// EIP-191 signature of `message`.
+    pub signature: String,
+}
+
+#[derive(Serialize)]
+pub struct TxErrorResponse {
+    pub tx_hash: String,
+    pub error: String,
+}
+
 #[derive(Deserialize)]
 pub struct CheckRedeemQuery {
     pub content: String,
@@ -90,17 +226,88 @@ pub struct CheckRedeemQuery {
 #[derive(Serialize)]
 pub struct CheckRedeemResponse {
     pub safe: bool,
+    pub verdict: PolicyVerdict,
 }
 
-#[derive(Clone)]
 pub struct SharedState<A: TeleportDB> {
     pub db: Arc<Mutex<A>>,
     pub signer: LocalSigner<SigningKey>,
     pub app_url: String,
     pub tee_url: String,
     pub twitter_builder: TwitterBuilder,
-    pub nft_action_sender: mpsc::Sender<(NFTAction, oneshot::Sender<String>)>,
+    pub tx_manager: Arc<TxManager>,
+    pub client_db: ClientDB,
+    pub eip712_domain: Eip712Domain,
     pub rpc_url: String,
+    pub policy_checker: Arc<dyn PolicyChecker>,
+    pub job_queue: Arc<JobQueue>,
+    pub subscriber_status: SubscriberStatusHandle,
+}
+
+impl<A: TeleportDB> Clone for SharedState<A> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            signer: self.signer.clone(),
+            app_url: self.app_url.clone(),
+            tee_url: self.tee_url.clone(),
+            twitter_builder: self.twitter_builder.clone(),
+            tx_manager: self.tx_manager.clone(),
+            client_db: self.client_db.clone(),
+            eip712_domain: self.eip712_domain.clone(),
+            rpc_url: self.rpc_url.clone(),
+            policy_checker: self.policy_checker.clone(),
+            job_queue: self.job_queue.clone(),
+            subscriber_status: self.subscriber_status.clone(),
+        }
+    }
+}
+
+/// Admin endpoints require `Authorization: Bearer $ADMIN_TOKEN` and are disabled when
+/// `ADMIN_TOKEN` is unset.
+fn check_admin_token(headers: &HeaderMap) -> Result<(), StatusCode> {
+    let admin_token = std::env::var("ADMIN_TOKEN").map_err(|_| StatusCode::FORBIDDEN)?;
+    let expected = format!("Bearer {}", admin_token);
+    match headers.get(http::header::AUTHORIZATION) {
+        Some(value) if constant_time_eq(value.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Compares two secrets in time that depends on neither where they differ nor their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Maps a failed mint or redeem transaction to the status returned to the frontend.
+fn tx_error_status(error: &eyre::Report) -> StatusCode {
+    match error.downcast_ref::<TxError>() {
+        Some(TxError::WouldRevert(_) | TxError::Reverted { .. }) => StatusCode::BAD_REQUEST,
+        Some(TxError::InsufficientFunds) => StatusCode::SERVICE_UNAVAILABLE,
+        // Still pending, so a retry would send a second transaction.
+        Some(TxError::Timeout { .. }) => StatusCode::GATEWAY_TIMEOUT,
+        Some(error) if error.is_retryable() => StatusCode::SERVICE_UNAVAILABLE,
+        Some(_) => StatusCode::BAD_GATEWAY,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Checks that the session cookie belongs to the X account of the user at `address`.
+async fn check_session<A: TeleportDB>(
+    db: &Mutex<A>,
+    jar: &CookieJar,
+    address: &str,
+) -> Result<User, StatusCode> {
+    let session_id = jar.get(SESSION_ID_COOKIE_NAME).ok_or(StatusCode::UNAUTHORIZED)?;
+    let db = db.lock().await;
+    let user = db.get_user_by_address(address.to_string()).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let session =
+        db.get_session(session_id.value().to_string()).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    if user.x_id.as_ref() != Some(&session.x_id) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(user)
 }
 
 pub async fn cookietest<A: TeleportDB>(
@@ -135,8 +342,7 @@ pub async fn register_or_login<A: TeleportDB>(
     existing_user.oauth_tokens = oauth_tokens.clone().into();
     db.add_user(address.clone(), existing_user).expect("Failed to add oauth tokens to database");
 
-    let url =
-        format!("https://api.twitter.com/oauth/authenticate?oauth_token={}", oauth_tokens.token);
+    let url = shared_state.twitter_builder.authenticate_url(&oauth_tokens.token);
 
     Redirect::temporary(&url)
 }
@@ -145,7 +351,7 @@ pub async fn callback<A: TeleportDB>(
     State(shared_state): State<SharedState<A>>,
     Query(query): Query<CallbackQuery>,
     jar: CookieJar,
-) -> (CookieJar, Redirect) {
+) -> Result<(CookieJar, Redirect), StatusCode> {
     let oauth_token = query.oauth_token;
     let oauth_verifier = query.oauth_verifier;
     let address = query.address;
@@ -169,25 +375,94 @@ pub async fn callback<A: TeleportDB>(
     let twitter_client = shared_state.twitter_builder.with_auth(token_pair);
     let x_info = twitter_client.get_user_info().await.expect("Failed to get user info");
 
+    // An address stays bound to the first X account it logged in with, also when logging in
+    // again after a revocation, so that its held jobs are released for that account.
+    if oauth_user.x_id.as_ref().is_some_and(|x_id| *x_id != x_info.id) {
+        log::info!(
+            "Rejected login of {} as X user {}, bound to {:?}",
+            address,
+            x_info.id,
+            oauth_user.x_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
     let session_id = db
         .add_session(Session { x_id: x_info.id.clone(), address: address.clone() })
         .expect("Failed to add session to database");
 
-    if oauth_user.x_id.is_none() {
-        oauth_user.x_id = Some(x_info.id.clone());
+    let reauthorized = oauth_user.reauth_required;
+    if oauth_user.x_id.is_none() || reauthorized {
+        oauth_user.x_id.get_or_insert_with(|| x_info.id.clone());
         oauth_user.access_tokens = Some(access_tokens);
-        db.add_user(address, oauth_user.clone()).expect("Failed to add user to database");
-        drop(db);
+        oauth_user.reauth_required = false;
+        db.add_user(address.clone(), oauth_user.clone()).expect("Failed to add user to database");
+    }
+    drop(db);
+    if reauthorized {
+        release_held_jobs(&shared_state, &x_info.id).await;
+    }
+
+    login_redirect(&shared_state, jar, session_id, &address, &x_info, &query.frontend_url).await
+}
+
+async fn release_held_jobs<A: TeleportDB>(shared_state: &SharedState<A>, x_id: &str) {
+    let released =
+        shared_state.job_queue.release_held(x_id).await.expect("Failed to release held jobs");
+    log::info!("User {} logged in again, released {} held jobs", x_id, released);
+}
+
+/// Sends a logged in user back to the frontend with their session cookie and an `AccountLink`
+/// signed by the enclave, which uses up the user's current link nonce. Only the X account the
+/// address is bound to is linked.
+async fn login_redirect<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    jar: CookieJar,
+    session_id: String,
+    address: &str,
+    x_info: &UserInfo,
+    frontend_url: &str,
+) -> Result<(CookieJar, Redirect), StatusCode> {
+    let account = Address::from_str(address).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let ttl = std::env::var("ACCOUNT_LINK_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_ACCOUNT_LINK_TTL_SECS);
+    let expiry = chrono::Utc::now().timestamp() as u64 + ttl;
+
+    let mut db = shared_state.db.lock().await;
+    let mut user =
+        db.get_user_by_address(address.to_string()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if user.x_id.as_deref() != Some(x_info.id.as_str()) {
+        log::info!("Refused to link {} to X user {}, bound to {:?}", address, x_info.id, user.x_id);
+        return Err(StatusCode::FORBIDDEN);
     }
+    let nonce = user.account_link_nonce;
+    user.account_link_nonce += 1;
+    db.add_user(address.to_string(), user).expect("Failed to add user to database");
+    drop(db);
 
-    let msg = format!("nonce={}&x_id={}", 0, x_info.id);
-    let sig = shared_state.signer.sign_message(msg.as_bytes()).await.unwrap();
+    let link = AccountLink::new(account, &x_info.id, nonce, expiry)
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    let sig = shared_state
+        .signer
+        .sign_hash(&link.eip712_signing_hash(&shared_state.eip712_domain))
+        .await
+        .map_err(|e| {
+            log::error!("Failed to sign account link: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     let encoded_x_info =
-        serde_urlencoded::to_string(&x_info).expect("Failed to encode x_info as query params");
-    let url_with_params =
-        format!("{}/create?sig={:?}&success=true&{}", query.frontend_url, sig, encoded_x_info);
-    (
+        serde_urlencoded::to_string(x_info).expect("Failed to encode x_info as query params");
+    let url_with_params = format!(
+        "{}/create?sig={}&nonce={}&expiry={}&success=true&{}",
+        frontend_url,
+        alloy::hex::encode_prefixed(sig.as_bytes()),
+        nonce,
+        expiry,
+        encoded_x_info
+    );
+    Ok((
         jar.add(
             Cookie::build((SESSION_ID_COOKIE_NAME, session_id))
                 .secure(true)
@@ -195,15 +470,116 @@ pub async fn callback<A: TeleportDB>(
                 .same_site(SameSite::None),
         ),
         Redirect::temporary(&url_with_params),
-    )
+    ))
 }
 
-pub async fn mint(
+fn oauth2_redirect_uri(tee_url: &str) -> String {
+    format!("https://{}/callbackOAuth2", tee_url)
+}
+
+/// Starts an OAuth 2.0 login, which grants the enclave narrower scopes than `/new`.
+pub async fn register_or_login_oauth2<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    Query(query): Query<NewUserQuery>,
+) -> Result<Redirect, StatusCode> {
+    let address = query.address;
+    let nonce = cuid::cuid2();
+    let state = format!("{}.{}", address, nonce);
+    let (url, pkce_verifier) = shared_state
+        .twitter_builder
+        .oauth2_authorize_url(&oauth2_redirect_uri(&shared_state.tee_url), &state)
+        .map_err(|e| {
+            log::error!("Failed to start OAuth 2.0 login: {:?}", e);
+            StatusCode::NOT_FOUND
+        })?;
+
+    let mut db = shared_state.db.lock().await;
+    let mut existing_user = db.get_user_by_address(address.clone()).ok().unwrap_or_default();
+    existing_user.oauth2_login = Some(OAuth2Login {
+        nonce,
+        pkce_verifier,
+        frontend_url: query.frontend_url.unwrap_or(shared_state.app_url),
+    });
+    db.add_user(address, existing_user).expect("Failed to add OAuth 2.0 login to database");
+    drop(db);
+
+    Ok(Redirect::temporary(&url))
+}
+
+pub async fn callback_oauth2<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    Query(query): Query<OAuth2CallbackQuery>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), StatusCode> {
+    let (address, nonce) = query.state.rsplit_once('.').ok_or(StatusCode::BAD_REQUEST)?;
+
+    let mut db = shared_state.db.lock().await;
+    let mut user =
+        db.get_user_by_address(address.to_string()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let login = user
+        .oauth2_login
+        .take()
+        .filter(|login| login.nonce == nonce)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    // Used up before the requests to X, so that the lock is not held across them.
+    db.add_user(address.to_string(), user).expect("Failed to add user to database");
+    drop(db);
+
+    let tokens = shared_state
+        .twitter_builder
+        .oauth2_exchange_code(
+            query.code,
+            oauth2_redirect_uri(&shared_state.tee_url),
+            login.pkce_verifier,
+        )
+        .await
+        .map_err(|e| {
+            log::error!("Failed to exchange OAuth 2.0 code: {:?}", e);
+            StatusCode::BAD_GATEWAY
+        })?;
+    let twitter_client =
+        shared_state.twitter_builder.with_oauth2_token(tokens.access_token.clone());
+    let x_info = twitter_client.get_user_info().await.map_err(|e| {
+        log::error!("Failed to get user info: {:?}", e);
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    let mut db = shared_state.db.lock().await;
+    let mut user =
+        db.get_user_by_address(address.to_string()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    // Like the OAuth 1.0a login, an address stays bound to the first X account it logged in with.
+    if user.x_id.as_ref().is_some_and(|x_id| *x_id != x_info.id) {
+        log::info!(
+            "Rejected login of {} as X user {}, bound to {:?}",
+            address,
+            x_info.id,
+            user.x_id
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let session_id = db
+        .add_session(Session { x_id: x_info.id.clone(), address: address.to_string() })
+        .expect("Failed to add session to database");
+
+    let reauthorized = user.reauth_required;
+    user.x_id = Some(x_info.id.clone());
+    user.oauth2_tokens = Some(tokens);
+    user.reauth_required = false;
+    db.add_user(address.to_string(), user).expect("Failed to add user to database");
+    drop(db);
+    if reauthorized {
+        release_held_jobs(&shared_state, &x_info.id).await;
+    }
+
+    login_redirect(&shared_state, jar, session_id, address, &x_info, &login.frontend_url).await
+}
+
+pub async fn mint<A: TeleportDB>(
     jar: CookieJar,
     headers: HeaderMap,
-    State(shared_state): State<SharedState<InMemoryDB>>,
+    State(shared_state): State<SharedState<A>>,
     Json(query): Json<MintQuery>,
-) -> Result<Json<TxHashResponse>, StatusCode> {
+) -> Result<axum::response::Response, StatusCode> {
     if let Some(referer) = headers.get("Referer") {
         let referer = referer.to_str().unwrap_or("");
         if !referer.starts_with(&format!("https://{}/approve", shared_state.tee_url)) {
@@ -212,24 +588,11 @@ pub async fn mint(
     } else {
         return Err(StatusCode::FORBIDDEN);
     }
-    let db = shared_state.db.lock().await;
-    let user =
-        db.get_user_by_address(query.address.clone()).expect("Failed to get user by address");
-
-    if let Some(session_id) = jar.get(SESSION_ID_COOKIE_NAME) {
-        let session_id = session_id.value();
-        let session = db.get_session(session_id.to_string()).expect("Failed to getsession");
-        if session.x_id != user.x_id.clone().unwrap() {
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    } else {
-        return Err(StatusCode::UNAUTHORIZED);
-    }
-    drop(db);
+    let user = check_session(&shared_state.db, &jar, &query.address).await?;
 
-    let client = shared_state
-        .twitter_builder
-        .with_auth(user.access_tokens.ok_or_eyre("User has no access tokens").unwrap().into());
+    let client = user_client(&shared_state.db, &shared_state.twitter_builder, user.clone())
+        .await
+        .expect("Failed to build X client");
 
     let user_info = client.get_user_info().await.expect("Failed to get user info");
 
@@ -241,54 +604,451 @@ pub async fn mint(
 
     let nft_id = format!("{:032x}", rand::random::<u128>());
 
-    let nft_action = NFTAction::Mint {
-        recipient: Address::from_str(&query.address).expect("Failed to parse user address"),
-        policy: query.policy,
-        x_id: user.x_id.expect("User x_id not set"),
-        name: user_info.name,
+    let pending = mint_nft(
+        &shared_state.tx_manager,
+        Address::from_str(&query.address).expect("Failed to parse user address"),
+        user.x_id.expect("User x_id not set"),
+        query.policy,
+        user_info.name,
         username,
-        pfp_url: user_info.profile_image_url.replace("_normal", "_400x400"),
-        nft_id: nft_id.clone(),
-    };
-
-    let (sender, tx_hash) = oneshot::channel();
-
-    shared_state.nft_action_sender.send((nft_action, sender)).await.unwrap();
-    let tx_hash = tx_hash.await.unwrap();
+        user_info.profile_image_url.replace("_normal", "_400x400"),
+        nft_id.clone(),
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Failed to send mint tx: {:?}", e);
+        tx_error_status(&e)
+    })?;
+    let tx_hash = pending.tx_hash.clone();
 
     let mut db = shared_state.db.lock().await;
-    db.add_pending_nft(tx_hash.clone(), PendingNFT { address: query.address, nft_id })
-        .expect("Failed to add pending NFT");
+    db.add_pending_nft(
+        tx_hash.clone(),
+        PendingNFT { address: query.address, nft_id: nft_id.clone() },
+    )
+    .expect("Failed to add pending NFT");
     drop(db);
 
-    Ok(Json(TxHashResponse { hash: tx_hash }))
+    if !query.wait {
+        tokio::spawn(track_mint(shared_state.db.clone(), pending));
+        return Ok(Json(TxHashResponse { hash: tx_hash }).into_response());
+    }
+    match wait_for_mint(&shared_state.db, pending, nft_id).await {
+        Ok(minted) => Ok(Json(minted).into_response()),
+        Err(e) => {
+            log::error!("Mint tx {} failed: {:?}", tx_hash, e);
+            let error = TxErrorResponse { tx_hash, error: e.to_string() };
+            Ok((tx_error_status(&e), Json(error)).into_response())
+        }
+    }
 }
 
 pub async fn redeem<A: TeleportDB>(
     State(shared_state): State<SharedState<A>>,
     Json(query): Json<RedeemQuery>,
-) -> Json<TxHashResponse> {
-    let token_id = get_token_id(shared_state.rpc_url, query.nft_id.clone())
-        .await
-        .unwrap_or_else(|_| panic!("Failed to get NFT by id {}", query.nft_id));
+) -> Result<Json<RedeemResponse>, StatusCode> {
+    if query.token_type == TokenType::Tweet {
+        if let Err(e) = RedeemContent::parse(&query.content).and_then(|content| content.tweet()) {
+            log::info!("Rejected invalid redeem content: {:?}", e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let token_id =
+        get_token_id(shared_state.rpc_url.clone(), query.nft_id.clone()).await.map_err(|e| {
+            log::error!("Failed to get NFT by id {}: {:?}", query.nft_id, e);
+            StatusCode::BAD_GATEWAY
+        })?;
     log::info!("redeem token_id: {}", token_id);
+    verify_redeem_authorization(&shared_state, &token_id, &query).await?;
+    // The pre-flight check rejects media the policy checker cannot judge; forced redeems skip it.
+    if query.token_type == TokenType::Tweet && query.force {
+        fetch_checkable_media(&shared_state, &query.content).await?;
+    }
+
+    let verdict = if query.token_type == TokenType::Tweet && !query.force {
+        let verdict = preflight_check(&shared_state, &query.nft_id, &token_id, &query.content)
+            .await
+            .map_err(|e| {
+                log::error!("Failed pre-flight check of NFT {} redeem: {:?}", token_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        if !verdict.safe {
+            log::info!("Pre-flight check rejected NFT {} redeem: {}", token_id, verdict.message);
+            return Ok(Json(RedeemResponse { hash: None, verdict: Some(verdict) }));
+        }
+        Some(verdict)
+    } else {
+        None
+    };
+
+    let pending = redeem_nft(
+        &shared_state.tx_manager,
+        token_id.clone(),
+        query.content.clone(),
+        query.token_type,
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Failed to send redeem tx for NFT {}: {:?}", token_id, e);
+        tx_error_status(&e)
+    })?;
+    let tx_hash = pending.tx_hash.clone();
+
+    let mut db = shared_state.db.lock().await;
+    db.set_redemption(Redemption {
+        token_type: query.token_type,
+        ..Redemption::pending(token_id, Some(tx_hash.clone()), query.content)
+    })
+    .expect("Failed to add pending redemption");
+    drop(db);
+    tokio::spawn(track_redeem(shared_state.db.clone(), vec![token_id], pending));
+
+    Ok(Json(RedeemResponse { hash: Some(tx_hash), verdict }))
+}
+
+/// Checks that the token's current owner signed an authorization for this redeem, and uses up
+/// its nonce.
+async fn verify_redeem_authorization<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    token_id: &str,
+    query: &RedeemQuery,
+) -> Result<(), StatusCode> {
+    let authorization = RedeemAuthorization::new(
+        U256::from_str(token_id).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        &query.content,
+        query.token_type.as_u8(),
+        query.nonce,
+        query.deadline,
+    );
+    let signer = authorization
+        .recover_signer(&shared_state.eip712_domain, &query.signature)
+        .map_err(|e| {
+            log::info!("Rejected redeem authorization for NFT {}: {:?}", token_id, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+    let owner =
+        get_owner_of(shared_state.rpc_url.clone(), token_id.to_string()).await.map_err(|e| {
+            log::error!("Failed to get owner of NFT {}: {:?}", token_id, e);
+            StatusCode::BAD_GATEWAY
+        })?;
+    if signer != owner {
+        log::info!("Rejected redeem of NFT {} signed by {}, owned by {}", token_id, signer, owner);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    shared_state.db.lock().await.use_redeem_nonce(owner.to_string(), query.nonce).map_err(|e| {
+        log::info!("Rejected redeem authorization for NFT {}: {:?}", token_id, e);
+        StatusCode::UNAUTHORIZED
+    })
+}
+
+/// Checks that the owner of the tokens, `owner`, signed an authorization for this batch redeem,
+/// and uses up its nonce.
+async fn verify_redeem_batch_authorization<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    owner: Address,
+    token_ids: &[String],
+    query: &RedeemBatchQuery,
+) -> Result<(), StatusCode> {
+    let token_ids = token_ids
+        .iter()
+        .map(|token_id| U256::from_str(token_id))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let authorization =
+        RedeemBatchAuthorization::new(token_ids, &query.content, query.nonce, query.deadline);
+    let signer = authorization
+        .recover_signer(&shared_state.eip712_domain, &query.signature)
+        .map_err(|e| {
+            log::info!("Rejected batch redeem authorization: {:?}", e);
+            StatusCode::UNAUTHORIZED
+        })?;
+    if signer != owner {
+        log::info!("Rejected batch redeem signed by {}, owned by {}", signer, owner);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    shared_state.db.lock().await.use_redeem_nonce(owner.to_string(), query.nonce).map_err(|e| {
+        log::info!("Rejected batch redeem authorization: {:?}", e);
+        StatusCode::UNAUTHORIZED
+    })
+}
+
+/// The nonce the owner's next redeem authorization must carry.
+pub async fn get_redeem_nonce<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    Query(query): Query<RedeemNonceQuery>,
+) -> Result<Json<RedeemNonceResponse>, StatusCode> {
+    let address = Address::from_str(&query.address).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let db = shared_state.db.lock().await;
+    let nonce =
+        db.get_redeem_nonce(address.to_string()).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(RedeemNonceResponse { nonce }))
+}
+
+/// The address a token posts as: its creator, whom the mint was recorded for, as in the
+/// `RedeemTweet` event. The index's `userId` follows transfers to the current holder.
+async fn token_creator<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    nft_id: &str,
+) -> eyre::Result<String> {
+    let db = shared_state.db.lock().await;
+    Ok(db.get_nft(nft_id.to_string())?.address)
+}
+
+/// Checks the content of a tweet redeem the way the event handler will once it is on-chain, and
+/// signs the verdict.
+async fn preflight_check<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    nft_id: &str,
+    token_id: &str,
+    content: &str,
+) -> eyre::Result<SignedVerdict> {
+    let tweet_content = RedeemContent::parse(content)?;
+    let creator = token_creator(shared_state, nft_id).await?;
+    let policy = shared_state.client_db.get_token_policy(token_id.to_string()).await?;
+    let (verdict, _) = check_redeem_content(
+        &shared_state.db,
+        &shared_state.twitter_builder,
+        shared_state.policy_checker.as_ref(),
+        &creator,
+        &tweet_content,
+        policy,
+    )
+    .await?;
+    sign_verdict(shared_state, token_id, content, verdict).await
+}
+
+/// Pre-flight checks a batch redeem like [`preflight_check`] does each token, fetching the media
+/// once and checking the content once per distinct policy and referenced tweet. `tokens` are
+/// pairs of NFT id and token id.
+async fn preflight_check_batch<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    tokens: &[(String, String)],
+    content: &str,
+) -> eyre::Result<BTreeMap<String, SignedVerdict>> {
+    let tweet_content = RedeemContent::parse(content)?;
+    let token_ids = tokens.iter().map(|(_, token_id)| token_id.clone()).collect::<Vec<_>>();
+    let mut indexed = shared_state.client_db.get_token_owners_and_policies(&token_ids).await?;
+    let (media, _) = fetch_media(&tweet_content).await?;
+
+    let mut contexts: HashMap<String, Option<TweetContext>> = HashMap::new();
+    let mut checked: HashMap<(String, Option<String>), PolicyVerdict> = HashMap::new();
+    let mut verdicts = BTreeMap::new();
+    for (nft_id, token_id) in tokens {
+        let (_, policy) =
+            indexed.remove(token_id).ok_or_else(|| eyre::eyre!("NFT {} not indexed", token_id))?;
+        // The referenced tweet is read as the account the token posts as.
+        let creator = token_creator(shared_state, nft_id).await?;
+        let context = match contexts.get(&creator) {
+            Some(context) => context.clone(),
+            None => {
+                let context = get_tweet_context(
+                    &shared_state.db,
+                    &shared_state.twitter_builder,
+                    &creator,
+                    &tweet_content,
+                )
+                .await?;
+                contexts.insert(creator, context.clone());
+                context
+            }
+        };
+        let key = (policy, context.as_ref().map(|context| context.text.clone()));
+        let verdict = match checked.get(&key) {
+            Some(verdict) => verdict.clone(),
+            None => {
+                let mut request = PolicyRequest::new(tweet_content.text.clone(), key.0.clone())
+                    .with_media(media.clone());
+                if let Some(context) = context {
+                    request = request.with_context(context);
+                }
+                let verdict = shared_state.policy_checker.check(&request).await?;
+                checked.insert(key, verdict.clone());
+                verdict
+            }
+        };
+        verdicts.insert(
+            token_id.clone(),
+            sign_verdict(shared_state, token_id, content, verdict).await?,
+        );
+    }
+    Ok(verdicts)
+}
+
+/// Signs a pre-flight verdict on redeeming `token_id` with `content`.
+async fn sign_verdict<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    token_id: &str,
+    content: &str,
+    verdict: PolicyVerdict,
+) -> eyre::Result<SignedVerdict> {
+    let safe = verdict.is_safe();
+    let message =
+        format!("token_id={}&content_hash={}&safe={}", token_id, keccak256(content), safe);
+    let signature = shared_state.signer.sign_message(message.as_bytes()).await?;
+    Ok(SignedVerdict {
+        safe,
+        verdict,
+        message,
+        signature: alloy::hex::encode_prefixed(signature.as_bytes()),
+    })
+}
+
+pub async fn redeem_batch<A: TeleportDB>(
+    jar: CookieJar,
+    State(shared_state): State<SharedState<A>>,
+    Json(query): Json<RedeemBatchQuery>,
+) -> Result<Json<RedeemBatchResponse>, StatusCode> {
+    check_session(&shared_state.db, &jar, &query.address).await?;
+    let unique_nft_ids = query.nft_ids.iter().collect::<HashSet<_>>();
+    if query.nft_ids.is_empty() ||
+        query.nft_ids.len() > MAX_BATCH_SIZE ||
+        unique_nft_ids.len() != query.nft_ids.len()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Err(e) = RedeemContent::parse(&query.content).and_then(|content| content.tweet()) {
+        log::info!("Rejected invalid batch redeem content: {:?}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let address = Address::from_str(&query.address).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let owned_tokens: Vec<(String, Address)> = stream::iter(query.nft_ids.iter().map(|nft_id| {
+        let rpc_url = shared_state.rpc_url.clone();
+        async move {
+            let token_id = get_token_id(rpc_url.clone(), nft_id.clone()).await.map_err(|e| {
+                log::error!("Failed to get NFT by id {}: {:?}", nft_id, e);
+                StatusCode::BAD_GATEWAY
+            })?;
+            let owner = get_owner_of(rpc_url, token_id.clone()).await.map_err(|e| {
+                log::error!("Failed to get owner of NFT {}: {:?}", token_id, e);
+                StatusCode::BAD_GATEWAY
+            })?;
+            Ok::<_, StatusCode>((token_id, owner))
+        }
+    }))
+    .buffered(BATCH_RPC_CONCURRENCY)
+    .try_collect()
+    .await?;
+    let mut token_ids = vec![];
+    for (token_id, owner) in owned_tokens {
+        if owner != address {
+            log::info!("Rejected batch redeem of NFT {} owned by {}", token_id, owner);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        token_ids.push(token_id);
+    }
+    verify_redeem_batch_authorization(&shared_state, address, &token_ids, &query).await?;
 
-    let nft_action = NFTAction::Redeem { token_id, content: query.content };
+    if query.force {
+        fetch_checkable_media(&shared_state, &query.content).await?;
+    }
+    let mut verdicts = BTreeMap::new();
+    if !query.force {
+        let tokens =
+            query.nft_ids.iter().cloned().zip(token_ids.iter().cloned()).collect::<Vec<_>>();
+        verdicts =
+            preflight_check_batch(&shared_state, &tokens, &query.content).await.map_err(|e| {
+                log::error!("Failed pre-flight check of batch redeem of {:?}: {:?}", token_ids, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        if verdicts.values().any(|verdict| !verdict.safe) {
+            log::info!("Pre-flight check rejected batch redeem of NFTs {:?}", token_ids);
+            return Ok(Json(RedeemBatchResponse { hash: None, verdicts }));
+        }
+    }
 
-    let (sender, tx_hash) = oneshot::channel();
+    let pending =
+        redeem_batch_nft(&shared_state.tx_manager, token_ids.clone(), query.content.clone())
+            .await
+            .map_err(|e| {
+                log::error!("Failed to send batch redeem tx for NFTs {:?}: {:?}", token_ids, e);
+                tx_error_status(&e)
+            })?;
+    let tx_hash = pending.tx_hash.clone();
 
-    shared_state.nft_action_sender.send((nft_action, sender)).await.unwrap();
-    let tx_hash = tx_hash.await.unwrap();
+    let mut db = shared_state.db.lock().await;
+    for token_id in token_ids.iter() {
+        db.set_redemption(Redemption::pending(
+            token_id.clone(),
+            Some(tx_hash.clone()),
+            query.content.clone(),
+        ))
+        .expect("Failed to add pending redemption");
+    }
+    drop(db);
+    tokio::spawn(track_redeem(shared_state.db.clone(), token_ids, pending));
 
-    Json(TxHashResponse { hash: tx_hash })
+    Ok(Json(RedeemBatchResponse { hash: Some(tx_hash), verdicts }))
+}
+
+pub async fn get_redemption_status<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    Query(query): Query<RedemptionStatusQuery>,
+) -> Result<Json<Redemption>, StatusCode> {
+    let db = shared_state.db.lock().await;
+    let redemption = db.get_redemption(query.token_id).map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok(Json(redemption))
+}
+
+/// Tells the frontend whether the user must log in again through `/new` before their pending
+/// redemptions can be posted.
+pub async fn get_reauth_status<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    Query(query): Query<ReauthStatusQuery>,
+) -> Result<Json<ReauthStatusResponse>, StatusCode> {
+    let db = shared_state.db.lock().await;
+    let user = db.get_user_by_address(query.address).map_err(|_| StatusCode::NOT_FOUND)?;
+    drop(db);
+    let held_token_ids = match &user.x_id {
+        Some(x_id) => shared_state
+            .job_queue
+            .held_jobs(x_id)
+            .await
+            .into_iter()
+            .map(|job| job.kind.token_id().to_string())
+            .collect(),
+        None => vec![],
+    };
+    Ok(Json(ReauthStatusResponse { reauth_required: user.reauth_required, held_token_ids }))
 }
 
 pub async fn check_redeem<A: TeleportDB>(
-    State(_): State<SharedState<A>>,
+    State(shared_state): State<SharedState<A>>,
     Json(query): Json<CheckRedeemQuery>,
-) -> Json<CheckRedeemResponse> {
-    let safe = oai::is_tweet_safe(&query.content, &query.policy).await;
-    Json(CheckRedeemResponse { safe })
+) -> Result<Json<CheckRedeemResponse>, StatusCode> {
+    let content = RedeemContent::parse(&query.content).map_err(|e| {
+        log::info!("Rejected malformed redeem content: {:?}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    if let Err(e) = content.tweet() {
+        log::info!("Rejected invalid tweet: {:?}", e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let media = fetch_checkable_media(&shared_state, &query.content).await?;
+    let request = PolicyRequest::new(content.text, query.policy).with_media(media);
+    let verdict = shared_state.policy_checker.check(&request).await.map_err(|e| {
+        log::error!("Failed to check redeem content: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(CheckRedeemResponse { safe: verdict.is_safe(), verdict }))
+}
+
+/// Fetches the redeem content's media, rejecting media the policy checker cannot judge before
+/// a token is burned for it.
+async fn fetch_checkable_media<A: TeleportDB>(
+    shared_state: &SharedState<A>,
+    content: &str,
+) -> Result<Vec<PolicyMedia>, StatusCode> {
+    let content = RedeemContent::parse(content).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let (media, _) = fetch_media(&content).await.map_err(|e| {
+        log::info!("Rejected redeem media: {:?}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    if let Some(media) = media.iter().find(|media| !shared_state.policy_checker.can_check(media)) {
+        log::info!("Rejected {} media the policy checker cannot judge", media.mime_type);
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    Ok(media)
 }
 
 pub async fn get_tweet_id<A: TeleportDB>(
@@ -321,6 +1081,34 @@ pub async fn get_tweet_id<A: TeleportDB>(
     Json(TweetIdResponse { tweet_id })
 }
 
+pub async fn get_subscriber_status<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+) -> Json<SubscriberStatus> {
+    Json(shared_state.subscriber_status.lock().await.clone())
+}
+
+pub async fn get_dead_jobs<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Job>>, StatusCode> {
+    check_admin_token(&headers)?;
+    Ok(Json(shared_state.job_queue.dead_jobs().await))
+}
+
+pub async fn redrive_dead_jobs<A: TeleportDB>(
+    State(shared_state): State<SharedState<A>>,
+    headers: HeaderMap,
+    Query(query): Query<RedriveQuery>,
+) -> Result<Json<RedriveResponse>, StatusCode> {
+    check_admin_token(&headers)?;
+    let redriven = shared_state.job_queue.redrive_dead(query.id).await.map_err(|e| {
+        log::error!("Failed to redrive dead jobs: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    log::info!("Redrove {} dead jobs", redriven.len());
+    Ok(Json(RedriveResponse { redriven: redriven.into_iter().map(|job| job.id).collect() }))
+}
+
 pub async fn approve_mint<A: TeleportDB>(
     State(shared_state): State<SharedState<A>>,
     Query(query): Query<MintQuery>,
@@ -347,3 +1135,28 @@ pub async fn hello_world() -> &'static str {
     log::info!("Hello, World!");
     "Hello, World!"
 }
+
+/// The service's routes.
+pub fn router<A: TeleportDB>(shared_state: SharedState<A>) -> Router {
+    Router::new()
+        .route("/new", get(register_or_login))
+        .route("/approve", get(approve_mint))
+        .route("/callback", get(callback))
+        .route("/newOAuth2", get(register_or_login_oauth2))
+        .route("/callbackOAuth2", get(callback_oauth2))
+        .route("/cookietest", get(cookietest))
+        .route("/mint", post(mint))
+        .route("/redeem", post(redeem))
+        .route("/redeemBatch", post(redeem_batch))
+        .route("/redeemNonce", get(get_redeem_nonce))
+        .route("/checkRedeem", post(check_redeem))
+        .route("/tweetId", get(get_tweet_id))
+        .route("/redemptionStatus", get(get_redemption_status))
+        .route("/reauthStatus", get(get_reauth_status))
+        .route("/subscriberStatus", get(get_subscriber_status))
+        .route("/admin/deadJobs", get(get_dead_jobs))
+        .route("/admin/redrive", post(redrive_dead_jobs))
+        .route("/", get(hello_world))
+        .layer(CorsLayer::permissive())
+        .with_state(shared_state)
+}
@@ -1 +1,10 @@
+pub mod actions;
+pub mod content;
+pub mod db;
+pub mod eip712;
+pub mod endpoints;
+pub mod media_fetch;
+pub mod oai;
+pub mod policy;
+pub mod templates;
 pub mod twitter;
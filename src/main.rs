@@ -1,40 +1,38 @@
 use std::{net::SocketAddr, path::Path, sync::Arc};
 
 use acme_lib::create_rsa_key;
-use alloy::signers::local::PrivateKeySigner;
-use tokio::{sync::{mpsc,oneshot}, time::Duration};
+use alloy::{providers::Provider, signers::local::PrivateKeySigner};
+use tokio::{sync::oneshot, time::Duration};
 
 use rand::Rng;
 use axum::extract::{State};
 use axum_server::tls_rustls::RustlsConfig;
-use endpoints::{
-    approve_mint, callback, cookietest, get_tweet_id, hello_world, mint, redeem, register_or_login,
-    SharedState,
-};
 use openssl::pkey::{PKey,Private};
 use tokio::{fs, sync::Mutex, time::sleep};
 
-use tower_http::cors::CorsLayer;
-
-use crate::{
+use teleport::{
     actions::{
-        nft::{nft_action_consumer, subscribe_to_nft_events},
+        checkpoint::Checkpoint,
+        jobs::{run_job_worker, JobQueue, RetryConfig},
+        nft::get_nft_address,
+        subscriber::{run_event_subscriber, Confirmation, EventHandler, SubscriberStatus},
+        tx_manager::{TxConfig, TxManager},
         wallet::get_provider,
     },
-    cert::create_csr,
-    db::TeleportDB,
-    endpoints::check_redeem,
-    twitter::builder::TwitterBuilder,
+    db::{self, client_db::ClientDB, TeleportDB},
+    eip712::teleport_domain,
+    endpoints::{router, SharedState},
+    policy::policy_checker_from_env,
+    twitter::{
+        builder::{TwitterBuilder, XApiUrls},
+        oauth2::OAuth2Config,
+    },
 };
 
-mod actions;
+use crate::cert::create_csr;
+
 mod cert;
-mod db;
-mod endpoints;
-mod oai;
 mod sgx_attest;
-mod templates;
-pub mod twitter;
 
 const PRIVATE_KEY_PATH: &str = "/root/save/private_key.pem";
 const SHARED_KEY_PATH: &str = "/root/save/shared_key.pem";
@@ -43,6 +41,9 @@ const CSR_PATH: &str = "untrustedhost/request.csr";
 const QUOTE_PATH: &str = "untrustedhost/quote.dat";
 
 const WALLET_PATH: &str = "/root/shared/wallet.key";
+const JOBS_PATH: &str = "/root/shared/jobs.json";
+const CHECKPOINT_PATH: &str = "/root/shared/checkpoint.json";
+const SQLITE_PATH: &str = "/root/shared/teleport.db";
 
 async fn generate_or_read_privkey() -> PKey<Private> {
     let tee_url = std::env::var("TEE_URL").expect("TEE_URL not set");
@@ -160,7 +161,10 @@ async fn main() {
     let app_secret =
         std::env::var("TWITTER_CONSUMER_SECRET").expect("TWITTER_CONSUMER_SECRET not set");
 
-    let twitter_builder = TwitterBuilder::new(app_key, app_secret);
+    let twitter_builder = TwitterBuilder::new(app_key, app_secret)
+        .with_urls(XApiUrls::from_env())
+        .with_oauth2(OAuth2Config::from_env());
+    let policy_checker = policy_checker_from_env().expect("Failed to set up policy checker");
 
     let ws_rpc_url = ws_rpc_url + &rpc_key;
     let rpc_url = rpc_url + &rpc_key;
@@ -214,32 +218,37 @@ async fn main() {
     prepare_quote(&pkey, signer.address().to_string()).await;
 
     let provider = get_provider(rpc_url.clone(), signer.clone().into());
+    let chain_id = provider.get_chain_id().await.expect("Failed to get chain id");
+    let nft_address = get_nft_address().expect("Invalid NFT_ADDRESS");
+    let eip712_domain = teleport_domain(chain_id, nft_address, signer.address());
+    let tx_manager = Arc::new(TxManager::new(provider, signer.address(), TxConfig::from_env()));
+    ClientDB::new(database_url.clone())
+        .migrate()
+        .await
+        .expect("Failed to migrate the client database");
 
-    let db = db::in_memory::InMemoryDB::new();
+    let db = db::open_from_env(SQLITE_PATH).expect("Failed to open database");
     let db = Arc::new(Mutex::new(db));
-    let (sender, receiver) = mpsc::channel(100);
+    let job_queue = Arc::new(
+        JobQueue::open(JOBS_PATH, RetryConfig::from_env()).expect("Failed to open job queue"),
+    );
+    let subscriber_status = Arc::new(Mutex::new(SubscriberStatus::default()));
     let shared_state = SharedState {
         db: db.clone(),
         app_url,
         tee_url,
         signer,
         twitter_builder: twitter_builder.clone(),
-        nft_action_sender: sender,
-	rpc_url: rpc_url,
+        tx_manager,
+        client_db: ClientDB::new(database_url.clone()),
+        eip712_domain,
+	rpc_url: rpc_url.clone(),
+        policy_checker: policy_checker.clone(),
+        job_queue: job_queue.clone(),
+        subscriber_status: subscriber_status.clone(),
     };
 
-    let app = axum::Router::new()
-        .route("/new", axum::routing::get(register_or_login))
-        .route("/approve", axum::routing::get(approve_mint))
-        .route("/callback", axum::routing::get(callback))
-        .route("/cookietest", axum::routing::get(cookietest))
-        .route("/mint", axum::routing::post(mint))
-        .route("/redeem", axum::routing::post(redeem))
-        .route("/checkRedeem", axum::routing::post(check_redeem))
-        .route("/tweetId", axum::routing::get(get_tweet_id))
-        .route("/", axum::routing::get(hello_world))
-        .layer(CorsLayer::permissive())
-        .with_state(shared_state);
+    let app = router(shared_state);
 
     #[cfg(feature = "https")]
     {
@@ -260,9 +269,28 @@ async fn main() {
         });
     }
 
-    let db_clone = db.clone();
-    tokio::spawn(async move {
-        subscribe_to_nft_events(db_clone, twitter_builder, ws_rpc_url, database_url).await.unwrap();
-    });
-    nft_action_consumer(receiver, provider).await
+    // The worker only posts a redemption's tweet once because it stores the tweet id in the
+    // TeleportDB before anything else, so without a durable one a restart could post it again.
+    // Jobs stay queued on the encrypted mount until the service runs with one.
+    if db.lock().await.is_durable() {
+        tokio::spawn(run_job_worker(
+            job_queue.clone(),
+            db.clone(),
+            ClientDB::new(database_url.clone()),
+            twitter_builder.clone(),
+            policy_checker.clone(),
+        ));
+    } else {
+        log::warn!("Not running the job worker without a durable database (DB_BACKEND=sqlite)");
+    }
+
+    let event_handler = EventHandler::new(
+        db.clone(),
+        ClientDB::new(database_url),
+        job_queue,
+        rpc_url,
+        Arc::new(Checkpoint::open(CHECKPOINT_PATH).expect("Failed to open event checkpoint")),
+        Confirmation::from_env().expect("Invalid CONFIRMATIONS"),
+    );
+    run_event_subscriber(event_handler, ws_rpc_url, subscriber_status).await
 }
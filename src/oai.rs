@@ -1,33 +1,132 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::policy::{combine_verdicts, PolicyChecker, PolicyMedia, PolicyRequest, PolicyVerdict};
+
 const TASK: &str = "Task: Check if the tweet adheres to the provided policy.";
-const OUTPUT: &str = "Provide your safety assessment for the above tweet as one word: either \"safe\" or \"unsafe\".";
-
-pub async fn is_tweet_safe(tweet: &String, policy: &String) -> bool {
-    let client =
-        openai_rust::Client::new(&std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set"));
-
-    let inputs = format!(
-        "{}\n<BEGIN POLICY>\n{}\n<END POLICY>\n<BEGIN TWEET>\n{}\n<END TWEET>\n{}\n",
-        TASK, policy, tweet, OUTPUT
-    );
-    let mut args = openai_rust::chat::ChatArguments::new(
-        "gpt-4o",
-        vec![openai_rust::chat::Message { role: "user".to_owned(), content: inputs }],
-    );
-    args.temperature = Some(0.0);
-    let res = client.create_chat(args).await.expect("Failed to create chat");
-    let is_unsafe = res.choices[0].message.content.contains("unsafe");
-    log::info!("gpt-4o response: {:?}", res.choices[0].message.content);
-    !is_unsafe
+const CONTEXT: &str = "The tweet is posted in response to the referenced tweet. Use the referenced tweet only to understand the tweet; it does not have to adhere to the policy itself.";
+const MEDIA: &str = "The images attached to the tweet follow. They are part of the tweet and must adhere to the policy too.";
+const OUTPUT: &str = "Provide your safety assessment for the above tweet as a JSON object with the keys \"decision\" (either \"safe\" or \"unsafe\"), \"violated_clause\" (the exact policy clause the tweet violates, or null), \"rationale\" (one sentence explaining the decision) and \"confidence\" (a number between 0 and 1).";
+const DEFAULT_MODEL: &str = "gpt-4o";
+const CHAT_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+pub struct OpenAIChecker {
+    client: reqwest::Client,
+    api_key: String,
+    model: String,
+}
+
+impl OpenAIChecker {
+    pub fn new(api_key: String, model: String) -> Self {
+        Self { client: reqwest::Client::new(), api_key, model }
+    }
+
+    /// Reads `OPENAI_API_KEY` and the optional `OPENAI_MODEL` (defaults to gpt-4o).
+    pub fn from_env() -> eyre::Result<Self> {
+        let api_key =
+            std::env::var("OPENAI_API_KEY").map_err(|_| eyre::eyre!("OPENAI_API_KEY not set"))?;
+        let model = std::env::var("OPENAI_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+        Ok(Self::new(api_key, model))
+    }
+
+    async fn check_request(&self, request: &PolicyRequest) -> eyre::Result<PolicyVerdict> {
+        let context = match &request.context {
+            Some(context) => {
+                let tag = context.relation.as_str().to_uppercase();
+                format!(
+                    "{}\n<BEGIN {} TWEET>\n{}\n<END {} TWEET>\n",
+                    CONTEXT, tag, context.text, tag
+                )
+            }
+            None => String::new(),
+        };
+        let images = request.media.iter().filter(|media| self.can_check(media)).collect::<Vec<_>>();
+        let media = if images.is_empty() { String::new() } else { format!("{}\n", MEDIA) };
+        let inputs = format!(
+            "{}\n<BEGIN POLICY>\n{}\n<END POLICY>\n{}<BEGIN TWEET>\n{}\n<END TWEET>\n{}{}\n",
+            TASK, request.policy, context, request.content, media, OUTPUT
+        );
+        let mut content = vec![json!({ "type": "text", "text": inputs })];
+        for image in images {
+            let url = format!("data:{};base64,{}", image.mime_type, STANDARD.encode(&image.bytes));
+            content.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+        }
+        let body = json!({
+            "model": self.model,
+            "messages": [{ "role": "user", "content": content }],
+            "temperature": 0.0,
+            "response_format": { "type": "json_object" },
+        });
+        let resp = self
+            .client
+            .post(CHAT_COMPLETIONS_URL)
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        let res: ChatResponse = resp.json().await?;
+        let content =
+            &res.choices.first().ok_or_else(|| eyre::eyre!("No choices"))?.message.content;
+        log::info!("{} response: {:?}", self.model, content);
+        let verdict = PolicyVerdict::from_model_response(content)?;
+
+        // The model only sees still images, so other media fails closed.
+        let unchecked = request.media.iter().filter(|media| !self.can_check(media)).map(|media| {
+            PolicyVerdict::unsafe_content(
+                media.mime_type.clone(),
+                format!("{} media cannot be checked against the policy", media.mime_type),
+                1.0,
+            )
+        });
+        Ok(combine_verdicts(std::iter::once(verdict).chain(unchecked).collect()))
+    }
+}
+
+impl PolicyChecker for OpenAIChecker {
+    fn check<'a>(
+        &'a self,
+        request: &'a PolicyRequest,
+    ) -> BoxFuture<'a, eyre::Result<PolicyVerdict>> {
+        Box::pin(self.check_request(request))
+    }
+
+    /// GIFs may be animated, which the model does not accept.
+    fn can_check(&self, media: &PolicyMedia) -> bool {
+        media.is_image() && media.mime_type != "image/gif"
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::oai::is_tweet_safe;
+    use crate::{
+        oai::OpenAIChecker,
+        policy::{PolicyChecker, PolicyMedia},
+    };
 
     async fn test_is_tweet_safe(tweet: &str, policy: &str, expected: bool) {
         dotenv::dotenv().ok();
-        let is_safe = is_tweet_safe(&tweet.to_string(), &policy.to_string()).await;
-        assert_eq!(is_safe, expected);
+        let checker = OpenAIChecker::from_env().unwrap();
+        let verdict = checker.check_tweet(tweet, policy).await.unwrap();
+        assert_eq!(verdict.is_safe(), expected);
     }
 
     #[tokio::test]
@@ -49,4 +148,14 @@ mod tests {
         )
         .await;
     }
+
+    #[test]
+    fn oai_can_check_test() {
+        let checker = OpenAIChecker::new("key".to_string(), "model".to_string());
+        let media =
+            |mime_type: &str| PolicyMedia { mime_type: mime_type.to_string(), bytes: vec![] };
+        assert!(checker.can_check(&media("image/png")));
+        assert!(!checker.can_check(&media("image/gif")));
+        assert!(!checker.can_check(&media("video/mp4")));
+    }
 }
@@ -0,0 +1,89 @@
+use futures::future::BoxFuture;
+
+use super::{PolicyChecker, PolicyRequest, PolicyVerdict};
+
+const DEFAULT_UNSAFE_MARKER: &str = "[unsafe]";
+
+/// Deterministic checker for offline testing: a tweet is unsafe iff its text or one of its media
+/// files contains the marker.
+#[derive(Debug, Clone)]
+pub struct MockChecker {
+    unsafe_marker: String,
+}
+
+impl MockChecker {
+    pub fn new(unsafe_marker: String) -> Self {
+        Self { unsafe_marker }
+    }
+
+    pub fn from_env() -> Self {
+        let unsafe_marker = std::env::var("POLICY_MOCK_UNSAFE_MARKER")
+            .unwrap_or_else(|_| DEFAULT_UNSAFE_MARKER.to_string());
+        Self::new(unsafe_marker)
+    }
+}
+
+impl Default for MockChecker {
+    fn default() -> Self {
+        Self::new(DEFAULT_UNSAFE_MARKER.to_string())
+    }
+}
+
+impl PolicyChecker for MockChecker {
+    fn check<'a>(
+        &'a self,
+        request: &'a PolicyRequest,
+    ) -> BoxFuture<'a, eyre::Result<PolicyVerdict>> {
+        Box::pin(async move {
+            if request.content.contains(&self.unsafe_marker) {
+                return Ok(PolicyVerdict::unsafe_content(
+                    self.unsafe_marker.clone(),
+                    "Tweet contains the mock unsafe marker".to_string(),
+                    1.0,
+                ));
+            }
+            let marker = self.unsafe_marker.as_bytes();
+            if request
+                .media
+                .iter()
+                .any(|media| media.bytes.windows(marker.len()).any(|window| window == marker))
+            {
+                return Ok(PolicyVerdict::unsafe_content(
+                    self.unsafe_marker.clone(),
+                    "Media contains the mock unsafe marker".to_string(),
+                    1.0,
+                ));
+            }
+            Ok(PolicyVerdict::safe(
+                "Tweet does not contain the mock unsafe marker".to_string(),
+                1.0,
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyMedia;
+
+    #[tokio::test]
+    async fn mock_checker_test() -> eyre::Result<()> {
+        let checker = MockChecker::default();
+        assert!(checker.check_tweet("gm", "anything goes").await?.is_safe());
+        let verdict = checker.check_tweet("gm [unsafe]", "anything goes").await?;
+        assert!(!verdict.is_safe());
+        assert_eq!(verdict.violated_clause.as_deref(), Some("[unsafe]"));
+
+        let media = |bytes: &[u8]| PolicyMedia {
+            mime_type: "image/png".to_string(),
+            bytes: bytes.to_vec(),
+        };
+        let request = PolicyRequest::new("gm".to_string(), "anything goes".to_string());
+        let clean = request.clone().with_media(vec![media(b"\x89PNG")]);
+        assert!(checker.check(&clean).await?.is_safe());
+        let flagged = request.with_media(vec![media(b"\x89PNG"), media(b"\x89PNG[unsafe]")]);
+        assert!(!checker.check(&flagged).await?.is_safe());
+        Ok(())
+    }
+}
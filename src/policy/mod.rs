@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::oai::OpenAIChecker;
+
+use self::{mock::MockChecker, rules::RuleBasedChecker};
+
+pub mod mock;
+pub mod rules;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Safe,
+    Unsafe,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PolicyVerdict {
+    pub decision: Decision,
+    /// The policy clause the content violates, if any.
+    pub violated_clause: Option<String>,
+    pub rationale: String,
+    /// How sure the checker is of its decision, between 0 and 1.
+    pub confidence: f32,
+}
+
+impl PolicyVerdict {
+    pub fn safe(rationale: String, confidence: f32) -> Self {
+        Self { decision: Decision::Safe, violated_clause: None, rationale, confidence }
+    }
+
+    pub fn unsafe_content(violated_clause: String, rationale: String, confidence: f32) -> Self {
+        Self {
+            decision: Decision::Unsafe,
+            violated_clause: Some(violated_clause),
+            rationale,
+            confidence,
+        }
+    }
+
+    pub fn is_safe(&self) -> bool {
+        self.decision == Decision::Safe
+    }
+
+    /// Parses a verdict from a JSON-mode model response.
+    pub fn from_model_response(response: &str) -> eyre::Result<Self> {
+        let verdict: Self = serde_json::from_str(response.trim())?;
+        if !(0.0..=1.0).contains(&verdict.confidence) {
+            eyre::bail!("Verdict confidence out of range: {}", verdict.confidence);
+        }
+        Ok(verdict)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweetRelation {
+    Reply,
+    Quote,
+}
+
+impl TweetRelation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TweetRelation::Reply => "replied-to",
+            TweetRelation::Quote => "quoted",
+        }
+    }
+}
+
+/// The tweet a redeemed tweet replies to or quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TweetContext {
+    pub relation: TweetRelation,
+    pub text: String,
+}
+
+/// A media file attached to a tweet, after its type has been sniffed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PolicyMedia {
+    pub mime_type: String,
+    pub bytes: Vec<u8>,
+}
+
+impl std::fmt::Debug for PolicyMedia {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PolicyMedia")
+            .field("mime_type", &self.mime_type)
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+impl PolicyMedia {
+    pub fn is_image(&self) -> bool {
+        self.mime_type.starts_with("image/")
+    }
+}
+
+/// Everything a checker may look at to decide on a tweet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyRequest {
+    pub content: String,
+    pub policy: String,
+    pub context: Option<TweetContext>,
+    pub media: Vec<PolicyMedia>,
+}
+
+impl PolicyRequest {
+    pub fn new(content: String, policy: String) -> Self {
+        Self { content, policy, context: None, media: vec![] }
+    }
+
+    pub fn with_media(mut self, media: Vec<PolicyMedia>) -> Self {
+        self.media = media;
+        self
+    }
+
+    pub fn with_context(mut self, context: TweetContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+}
+
+/// Combines the verdicts on the parts of a tweet: it is unsafe if any part is, reporting the most
+/// confident violation.
+pub fn combine_verdicts(verdicts: Vec<PolicyVerdict>) -> PolicyVerdict {
+    let (unsafe_verdicts, safe_verdicts): (Vec<_>, Vec<_>) =
+        verdicts.into_iter().partition(|verdict| !verdict.is_safe());
+    if let Some(verdict) =
+        unsafe_verdicts.into_iter().max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+    {
+        return verdict;
+    }
+    let confidence = safe_verdicts.iter().map(|verdict| verdict.confidence).fold(1.0, f32::min);
+    let rationale =
+        safe_verdicts.into_iter().map(|verdict| verdict.rationale).collect::<Vec<_>>().join(" ");
+    PolicyVerdict::safe(rationale, confidence)
+}
+
+/// A moderation backend that decides whether a tweet adheres to a token's policy.
+pub trait PolicyChecker: Send + Sync + 'static {
+    fn check<'a>(
+        &'a self,
+        request: &'a PolicyRequest,
+    ) -> BoxFuture<'a, eyre::Result<PolicyVerdict>>;
+
+    /// Whether the checker can judge the media. Media it cannot judge makes the tweet unsafe.
+    fn can_check(&self, _media: &PolicyMedia) -> bool {
+        true
+    }
+
+    /// Checks a standalone tweet.
+    fn check_tweet<'a>(
+        &'a self,
+        tweet: &'a str,
+        policy: &'a str,
+    ) -> BoxFuture<'a, eyre::Result<PolicyVerdict>> {
+        Box::pin(async move {
+            self.check(&PolicyRequest::new(tweet.to_string(), policy.to_string())).await
+        })
+    }
+}
+
+/// Picks the moderation backend from `POLICY_CHECKER` (`openai`, `rules` or `mock`), defaulting
+/// to `openai`.
+pub fn policy_checker_from_env() -> eyre::Result<Arc<dyn PolicyChecker>> {
+    let backend = std::env::var("POLICY_CHECKER").unwrap_or_else(|_| "openai".to_string());
+    log::info!("Using policy checker: {}", backend);
+    let checker: Arc<dyn PolicyChecker> = match backend.as_str() {
+        "openai" => Arc::new(OpenAIChecker::from_env()?),
+        "rules" => Arc::new(RuleBasedChecker::from_env()?),
+        "mock" => Arc::new(MockChecker::from_env()),
+        other => eyre::bail!("Unknown policy checker: {}", other),
+    };
+    Ok(checker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verdict_from_model_response_test() -> eyre::Result<()> {
+        let verdict = PolicyVerdict::from_model_response(
+            r#"{"decision": "unsafe", "violated_clause": "Don't allow any criminal planning", "rationale": "The tweet plans a bank robbery.", "confidence": 0.97}"#,
+        )?;
+        assert!(!verdict.is_safe());
+        assert_eq!(verdict.violated_clause.as_deref(), Some("Don't allow any criminal planning"));
+
+        let verdict = PolicyVerdict::from_model_response(
+            r#"{"decision": "safe", "violated_clause": null, "rationale": "Harmless.", "confidence": 0.9}"#,
+        )?;
+        assert!(verdict.is_safe());
+
+        assert!(PolicyVerdict::from_model_response("safe").is_err());
+        assert!(PolicyVerdict::from_model_response(
+            r#"{"decision": "maybe", "violated_clause": null, "rationale": "", "confidence": 0.5}"#
+        )
+        .is_err());
+        assert!(PolicyVerdict::from_model_response(
+            r#"{"decision": "safe", "violated_clause": null, "rationale": "", "confidence": 7}"#
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn combine_verdicts_test() {
+        let verdict = combine_verdicts(vec![
+            PolicyVerdict::safe("Text is fine.".to_string(), 0.9),
+            PolicyVerdict::safe("Image is fine.".to_string(), 0.8),
+        ]);
+        assert!(verdict.is_safe());
+        assert_eq!(verdict.confidence, 0.8);
+
+        let verdict = combine_verdicts(vec![
+            PolicyVerdict::safe("Text is fine.".to_string(), 0.9),
+            PolicyVerdict::unsafe_content("no faces".to_string(), "A face.".to_string(), 0.6),
+            PolicyVerdict::unsafe_content("no cats".to_string(), "A cat.".to_string(), 0.95),
+        ]);
+        assert!(!verdict.is_safe());
+        assert_eq!(verdict.violated_clause.as_deref(), Some("no cats"));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+
+use futures::future::BoxFuture;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{PolicyChecker, PolicyRequest, PolicyVerdict};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RulesConfig {
+    #[serde(default)]
+    pub blocked_keywords: Vec<String>,
+    #[serde(default)]
+    pub blocked_patterns: Vec<String>,
+    /// Hex sha256 hashes of known-bad media files.
+    #[serde(default)]
+    pub blocked_media_hashes: Vec<String>,
+}
+
+/// Local checker that rejects tweets matching configured keywords or regexes, and media matching
+/// known-bad hashes. It ignores the natural-language policy, so it is a stand-in for when no model
+/// is available.
+#[derive(Debug)]
+pub struct RuleBasedChecker {
+    rules: Vec<Regex>,
+    blocked_media_hashes: HashSet<String>,
+}
+
+impl RuleBasedChecker {
+    pub fn new(config: RulesConfig) -> eyre::Result<Self> {
+        let keywords = config
+            .blocked_keywords
+            .iter()
+            .map(|keyword| format!(r"\b{}\b", regex::escape(keyword)));
+        let rules = keywords
+            .chain(config.blocked_patterns)
+            .map(|pattern| RegexBuilder::new(&pattern).case_insensitive(true).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        let blocked_media_hashes =
+            config.blocked_media_hashes.iter().map(|hash| hash.to_lowercase()).collect();
+        Ok(Self { rules, blocked_media_hashes })
+    }
+
+    /// Loads the rules from the JSON file at `POLICY_RULES_PATH`.
+    pub fn from_env() -> eyre::Result<Self> {
+        let path = std::env::var("POLICY_RULES_PATH")?;
+        let contents = std::fs::read_to_string(path)?;
+        Self::new(serde_json::from_str(&contents)?)
+    }
+}
+
+impl PolicyChecker for RuleBasedChecker {
+    fn check<'a>(
+        &'a self,
+        request: &'a PolicyRequest,
+    ) -> BoxFuture<'a, eyre::Result<PolicyVerdict>> {
+        Box::pin(async move {
+            for media in request.media.iter() {
+                let hash = hex::encode(Sha256::digest(&media.bytes));
+                if self.blocked_media_hashes.contains(&hash) {
+                    log::info!("Media matched blocked hash: {}", hash);
+                    return Ok(PolicyVerdict::unsafe_content(
+                        hash,
+                        "Media matched a blocked hash".to_string(),
+                        1.0,
+                    ));
+                }
+            }
+            if let Some(rule) = self.rules.iter().find(|rule| rule.is_match(&request.content)) {
+                log::info!("Tweet matched blocked rule: {}", rule.as_str());
+                return Ok(PolicyVerdict::unsafe_content(
+                    rule.as_str().to_string(),
+                    "Tweet matched a blocked rule".to_string(),
+                    1.0,
+                ));
+            }
+            Ok(PolicyVerdict::safe("Tweet matched no blocked rules".to_string(), 1.0))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyMedia;
+
+    #[tokio::test]
+    async fn rules_checker_test() -> eyre::Result<()> {
+        let checker = RuleBasedChecker::new(RulesConfig {
+            blocked_keywords: vec!["rob".to_string()],
+            blocked_patterns: vec![r"0x[0-9a-f]{40}".to_string()],
+            blocked_media_hashes: vec![hex::encode(Sha256::digest(b"bad image"))],
+        })?;
+        let policy = "Don't allow any criminal planning or criminal activity.";
+        let verdict = checker.check_tweet("I am going to ROB a bank.", policy).await?;
+        assert!(!verdict.is_safe());
+        assert_eq!(verdict.violated_clause.as_deref(), Some(r"\brob\b"));
+        let verdict = checker
+            .check_tweet("send to 0x36e7fda8cc503d5ec7729a42eb86ef02af315bf9", policy)
+            .await?;
+        assert!(!verdict.is_safe());
+        assert!(checker.check_tweet("I am going to cry about my robe.", policy).await?.is_safe());
+
+        let media = |bytes: &[u8]| PolicyMedia {
+            mime_type: "image/png".to_string(),
+            bytes: bytes.to_vec(),
+        };
+        let request = PolicyRequest::new("gm".to_string(), policy.to_string());
+        assert!(checker
+            .check(&request.clone().with_media(vec![media(b"good image")]))
+            .await?
+            .is_safe());
+        assert!(!checker.check(&request.with_media(vec![media(b"bad image")])).await?.is_safe());
+        Ok(())
+    }
+}
@@ -25,6 +25,7 @@ pub struct TwitterTokenPair {
 }
 
 pub async fn request_oauth_token(
+    api_url: &str,
     app_key: String,
     app_secret: String,
     callback_url: String,
@@ -32,7 +33,7 @@ pub async fn request_oauth_token(
     let secrets = reqwest_oauth1::Secrets::new(app_key, app_secret);
     let query = RequestTokenRequestQuery { oauth_callback: callback_url.to_string() };
     let response = reqwest_oauth1::Client::new()
-        .post("https://api.twitter.com/oauth/request_token")
+        .post(format!("{}/oauth/request_token", api_url))
         .sign(secrets)
         .query(&query)
         .generate_signature()?
@@ -66,6 +67,7 @@ struct AccessTokenRequestQuery {
 }
 
 pub async fn authorize_token(
+    api_url: &str,
     app_key: String,
     app_secret: String,
     oauth_token: String,
@@ -78,7 +80,7 @@ pub async fn authorize_token(
         reqwest_oauth1::Secrets::new(app_key, app_secret).token(oauth_token, oauth_token_secret);
 
     let response = reqwest_oauth1::Client::new()
-        .post("https://api.twitter.com/oauth/access_token")
+        .post(format!("{}/oauth/access_token", api_url))
         .sign(secrets)
         .query(&query)
         .generate_signature()?
@@ -1,21 +1,186 @@
+use std::sync::Arc;
+
 use oauth1_request::signature_method::hmac_sha1::HmacSha1;
+use reqwest::{header::HeaderName, IntoUrl, Response};
 use reqwest_oauth1::{Client, OAuthClientProvider, Secrets, Signer};
+use serde::Serialize;
+
+use super::{
+    auth::{self, TwitterTokenPair},
+    oauth2::{self, OAuth2Config, OAuth2Tokens, PkceChallenge},
+    rate_limit::RateLimiter,
+};
+
+type OAuth1Signer<'a> = Signer<'a, Secrets<'a>, HmacSha1>;
+
+/// Where the X API lives. Point these at a local mock server to run offline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XApiUrls {
+    /// Serves the v2 API and the OAuth endpoints.
+    pub api: String,
+    pub upload: String,
+    /// Serves the OAuth 2.0 consent page.
+    pub web: String,
+}
 
-use super::auth::{self, TwitterTokenPair};
+impl Default for XApiUrls {
+    fn default() -> Self {
+        Self {
+            api: "https://api.twitter.com".to_string(),
+            upload: "https://upload.twitter.com".to_string(),
+            web: "https://twitter.com".to_string(),
+        }
+    }
+}
+
+impl XApiUrls {
+    /// Reads `X_API_URL`, `X_UPLOAD_URL` and `X_WEB_URL`, falling back to the real X hosts for any
+    /// that are unset.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str, default: String| std::env::var(name).unwrap_or(default);
+        Self {
+            api: var("X_API_URL", default.api),
+            upload: var("X_UPLOAD_URL", default.upload),
+            web: var("X_WEB_URL", default.web),
+        }
+    }
+
+    /// Serves everything from one host, as the mock server does.
+    pub fn single_host(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        Self { api: base_url.clone(), upload: base_url.clone(), web: base_url }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TwitterBuilder {
     pub consumer_key: String,
     pub consumer_secret: String,
+    pub urls: XApiUrls,
+    /// Shared by every client built from this builder.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Set to offer the narrower OAuth 2.0 login next to OAuth 1.0a.
+    pub oauth2: Option<OAuth2Config>,
+}
+
+/// An HTTP client that authenticates as the user, either by signing requests with OAuth 1.0a
+/// tokens or with an OAuth 2.0 bearer token.
+pub enum AuthClient<'a> {
+    OAuth1(Client<OAuth1Signer<'a>>),
+    OAuth2 { client: reqwest::Client, access_token: String },
+}
+
+pub enum AuthRequest<'a> {
+    OAuth1(reqwest_oauth1::RequestBuilder<OAuth1Signer<'a>>),
+    OAuth2(reqwest::RequestBuilder),
 }
 
 pub struct TwitterClient<'a> {
-    pub client: Client<Signer<'a, Secrets<'a>, HmacSha1>>,
+    pub client: AuthClient<'a>,
+    pub(super) rate_limiter: &'a RateLimiter,
+    /// Identifies the user for per-user rate limits.
+    pub(super) user_key: String,
+    pub(super) urls: &'a XApiUrls,
+}
+
+impl TwitterClient<'_> {
+    pub(super) fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.urls.api, path)
+    }
+
+    pub(super) fn upload_url(&self) -> String {
+        format!("{}/1.1/media/upload.json", self.urls.upload)
+    }
+}
+
+impl<'a> AuthClient<'a> {
+    pub fn get(&self, url: impl IntoUrl + Clone) -> AuthRequest<'a> {
+        match self {
+            AuthClient::OAuth1(client) => AuthRequest::OAuth1(client.get(url)),
+            AuthClient::OAuth2 { client, access_token } => {
+                AuthRequest::OAuth2(client.get(url).bearer_auth(access_token))
+            }
+        }
+    }
+
+    pub fn post(&self, url: impl IntoUrl + Clone) -> AuthRequest<'a> {
+        match self {
+            AuthClient::OAuth1(client) => AuthRequest::OAuth1(client.post(url)),
+            AuthClient::OAuth2 { client, access_token } => {
+                AuthRequest::OAuth2(client.post(url).bearer_auth(access_token))
+            }
+        }
+    }
+
+    pub fn is_oauth2(&self) -> bool {
+        matches!(self, AuthClient::OAuth2 { .. })
+    }
+}
+
+impl AuthRequest<'_> {
+    pub fn header(self, key: HeaderName, value: &'static str) -> Self {
+        match self {
+            AuthRequest::OAuth1(request) => AuthRequest::OAuth1(request.header(key, value)),
+            AuthRequest::OAuth2(request) => AuthRequest::OAuth2(request.header(key, value)),
+        }
+    }
+
+    pub fn body(self, body: String) -> Self {
+        match self {
+            AuthRequest::OAuth1(request) => AuthRequest::OAuth1(request.body(body)),
+            AuthRequest::OAuth2(request) => AuthRequest::OAuth2(request.body(body)),
+        }
+    }
+
+    pub fn form<T: Serialize + ?Sized>(self, form: &T) -> Self {
+        match self {
+            AuthRequest::OAuth1(request) => AuthRequest::OAuth1(request.form(form)),
+            AuthRequest::OAuth2(request) => AuthRequest::OAuth2(request.form(form)),
+        }
+    }
+
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> Self {
+        match self {
+            AuthRequest::OAuth1(request) => AuthRequest::OAuth1(request.query(query)),
+            AuthRequest::OAuth2(request) => AuthRequest::OAuth2(request.query(query)),
+        }
+    }
+
+    pub fn multipart(self, form: reqwest::multipart::Form) -> Self {
+        match self {
+            AuthRequest::OAuth1(request) => AuthRequest::OAuth1(request.multipart(form)),
+            AuthRequest::OAuth2(request) => AuthRequest::OAuth2(request.multipart(form)),
+        }
+    }
+
+    pub async fn send(self) -> eyre::Result<Response> {
+        Ok(match self {
+            AuthRequest::OAuth1(request) => request.send().await?,
+            AuthRequest::OAuth2(request) => request.send().await?,
+        })
+    }
 }
 
 impl TwitterBuilder {
     pub fn new(consumer_key: String, consumer_secret: String) -> Self {
-        Self { consumer_key, consumer_secret }
+        Self {
+            consumer_key,
+            consumer_secret,
+            urls: Default::default(),
+            rate_limiter: Default::default(),
+            oauth2: None,
+        }
+    }
+
+    pub fn with_urls(mut self, urls: XApiUrls) -> Self {
+        self.urls = urls;
+        self
+    }
+
+    pub fn with_oauth2(mut self, config: Option<OAuth2Config>) -> Self {
+        self.oauth2 = config;
+        self
     }
 
     pub async fn request_oauth_token(
@@ -23,6 +188,7 @@ impl TwitterBuilder {
         callback_url: String,
     ) -> eyre::Result<TwitterTokenPair> {
         auth::request_oauth_token(
+            &self.urls.api,
             self.consumer_key.clone(),
             self.consumer_secret.clone(),
             callback_url,
@@ -37,6 +203,7 @@ impl TwitterBuilder {
         oauth_verifier: String,
     ) -> eyre::Result<TwitterTokenPair> {
         auth::authorize_token(
+            &self.urls.api,
             self.consumer_key.clone(),
             self.consumer_secret.clone(),
             oauth_token,
@@ -46,16 +213,76 @@ impl TwitterBuilder {
         .await
     }
 
+    /// Where to send the user to approve an OAuth 1.0a request token.
+    pub fn authenticate_url(&self, oauth_token: &str) -> String {
+        format!("{}/oauth/authenticate?oauth_token={}", self.urls.api, oauth_token)
+    }
+
+    fn oauth2_config(&self) -> eyre::Result<&OAuth2Config> {
+        self.oauth2.as_ref().ok_or_else(|| eyre::eyre!("OAuth 2.0 is not configured"))
+    }
+
+    /// Starts an OAuth 2.0 login. Returns the URL to send the user to and the PKCE verifier to
+    /// keep until the callback.
+    pub fn oauth2_authorize_url(
+        &self,
+        redirect_uri: &str,
+        state: &str,
+    ) -> eyre::Result<(String, String)> {
+        let pkce = PkceChallenge::new();
+        let url = oauth2::authorize_url(
+            &self.urls.web,
+            self.oauth2_config()?,
+            redirect_uri,
+            state,
+            &pkce,
+        )?;
+        Ok((url, pkce.verifier))
+    }
+
+    pub async fn oauth2_exchange_code(
+        &self,
+        code: String,
+        redirect_uri: String,
+        verifier: String,
+    ) -> eyre::Result<OAuth2Tokens> {
+        oauth2::exchange_code(&self.urls.api, self.oauth2_config()?, code, redirect_uri, verifier)
+            .await
+    }
+
+    pub async fn oauth2_refresh(&self, tokens: &OAuth2Tokens) -> eyre::Result<OAuth2Tokens> {
+        oauth2::refresh(&self.urls.api, self.oauth2_config()?, tokens).await
+    }
+
     // pub fn from_access_tokens(tokens: AccessTokens) -> Self {
 
     // }
 
     pub fn with_auth(&self, tokens: TwitterTokenPair) -> TwitterClient {
+        let user_key = tokens.token.clone();
         let secrets = Secrets::new(self.consumer_key.clone(), self.consumer_secret.clone())
             .token(tokens.token, tokens.secret);
 
         let client = reqwest::Client::new();
         // client.oauth1(secrets)
-        TwitterClient { client: client.oauth1(secrets) }
+        TwitterClient {
+            client: AuthClient::OAuth1(client.oauth1(secrets)),
+            rate_limiter: &self.rate_limiter,
+            user_key,
+            urls: &self.urls,
+        }
+    }
+
+    /// Builds a client from an OAuth 2.0 access token, which the caller must have refreshed.
+    pub fn with_oauth2_token(&self, access_token: String) -> TwitterClient {
+        TwitterClient {
+            client: AuthClient::OAuth2 {
+                client: reqwest::Client::new(),
+                access_token: access_token.clone(),
+            },
+            rate_limiter: &self.rate_limiter,
+            user_key: access_token,
+            urls: &self.urls,
+        }
     }
 }
@@ -0,0 +1,189 @@
+use std::{fmt, future::Future};
+
+use reqwest::{header::HeaderMap, Response, StatusCode};
+
+use super::builder::TwitterClient;
+
+/// How long to back off after a 429 that did not say when the limit resets.
+const DEFAULT_RATE_LIMIT_SECS: i64 = 15 * 60;
+
+/// A failed X API call, classified by what the caller should do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TwitterError {
+    /// Try again at `reset` (unix seconds).
+    RateLimited { reset: i64 },
+    /// The user revoked the app or the tokens expired; they need to log in again.
+    AuthRevoked,
+    /// X refused the tweet as a duplicate of a recent one.
+    Duplicate,
+    /// The account is suspended or locked.
+    Suspended,
+    /// Network errors and X server errors, worth retrying.
+    Transient(String),
+    /// Any other rejected request, not worth retrying.
+    Api { status: u16, message: String },
+}
+
+impl TwitterError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, TwitterError::RateLimited { .. } | TwitterError::Transient(_))
+    }
+
+    /// Classifies an unsuccessful response.
+    pub fn from_response(status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        let lower = body.to_lowercase();
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => TwitterError::RateLimited {
+                reset: rate_limit_reset(headers)
+                    .unwrap_or_else(|| chrono::Utc::now().timestamp() + DEFAULT_RATE_LIMIT_SECS),
+            },
+            StatusCode::UNAUTHORIZED => TwitterError::AuthRevoked,
+            _ if lower.contains("duplicate") => TwitterError::Duplicate,
+            _ if lower.contains("suspended") || lower.contains("locked") => TwitterError::Suspended,
+            _ if status.is_server_error() => {
+                TwitterError::Transient(format!("{}: {}", status, body))
+            }
+            _ => TwitterError::Api { status: status.as_u16(), message: body.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for TwitterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TwitterError::RateLimited { reset } => write!(f, "Rate limited until {}", reset),
+            TwitterError::AuthRevoked => write!(f, "X access was revoked"),
+            TwitterError::Duplicate => write!(f, "Duplicate content"),
+            TwitterError::Suspended => write!(f, "Account is suspended"),
+            TwitterError::Transient(message) => write!(f, "Transient X error: {}", message),
+            TwitterError::Api { status, message } => {
+                write!(f, "X API error {}: {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TwitterError {}
+
+fn header_i64(headers: &HeaderMap, name: &str) -> Option<i64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+fn rate_limit_reset(headers: &HeaderMap) -> Option<i64> {
+    // The app-wide daily cap outlasts the endpoint window when both are exhausted.
+    if header_i64(headers, "x-app-limit-24hour-remaining") == Some(0) {
+        if let Some(reset) = header_i64(headers, "x-app-limit-24hour-reset") {
+            return Some(reset);
+        }
+    }
+    header_i64(headers, "x-rate-limit-reset")
+}
+
+/// A rate limit that a response says is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExhaustedLimit {
+    pub app_wide: bool,
+    pub reset: i64,
+}
+
+/// Reads the `x-rate-limit-*` and `x-app-limit-24hour-*` headers of any response.
+pub fn exhausted_limit(headers: &HeaderMap) -> Option<ExhaustedLimit> {
+    if header_i64(headers, "x-app-limit-24hour-remaining") == Some(0) {
+        let reset = header_i64(headers, "x-app-limit-24hour-reset")?;
+        return Some(ExhaustedLimit { app_wide: true, reset });
+    }
+    if header_i64(headers, "x-rate-limit-remaining") == Some(0) {
+        let reset = header_i64(headers, "x-rate-limit-reset")?;
+        return Some(ExhaustedLimit { app_wide: false, reset });
+    }
+    None
+}
+
+impl TwitterClient<'_> {
+    /// Sends a request built on this client, honoring known rate limits and classifying failures.
+    pub(super) async fn send<E: fmt::Display>(
+        &self,
+        request: impl Future<Output = Result<Response, E>>,
+    ) -> Result<Response, TwitterError> {
+        if let Some(reset) = self.rate_limiter.blocked_until(&self.user_key) {
+            return Err(TwitterError::RateLimited { reset });
+        }
+        let resp = request.await.map_err(|e| TwitterError::Transient(e.to_string()))?;
+        let status = resp.status();
+        if let Some(limit) = exhausted_limit(resp.headers()) {
+            self.rate_limiter.block(&self.user_key, limit);
+        }
+        if status.is_success() {
+            return Ok(resp);
+        }
+        let headers = resp.headers().clone();
+        let body = resp.text().await.unwrap_or_default();
+        let error = TwitterError::from_response(status, &headers, &body);
+        if let TwitterError::RateLimited { reset } = error {
+            let app_wide = header_i64(&headers, "x-app-limit-24hour-remaining") == Some(0);
+            self.rate_limiter.block(&self.user_key, ExhaustedLimit { app_wide, reset });
+        }
+        log::error!("X request failed: {}", error);
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn classify_test() {
+        let limited = headers(&[("x-rate-limit-remaining", "0"), ("x-rate-limit-reset", "1700")]);
+        assert_eq!(
+            TwitterError::from_response(StatusCode::TOO_MANY_REQUESTS, &limited, ""),
+            TwitterError::RateLimited { reset: 1700 }
+        );
+        let app_limited = headers(&[
+            ("x-rate-limit-reset", "1700"),
+            ("x-app-limit-24hour-remaining", "0"),
+            ("x-app-limit-24hour-reset", "9000"),
+        ]);
+        assert_eq!(
+            TwitterError::from_response(StatusCode::TOO_MANY_REQUESTS, &app_limited, ""),
+            TwitterError::RateLimited { reset: 9000 }
+        );
+        let empty = HeaderMap::new();
+        assert_eq!(
+            TwitterError::from_response(StatusCode::UNAUTHORIZED, &empty, "Unauthorized"),
+            TwitterError::AuthRevoked
+        );
+        assert_eq!(
+            TwitterError::from_response(
+                StatusCode::FORBIDDEN,
+                &empty,
+                r#"{"detail":"You are not allowed to create a Tweet with duplicate content."}"#
+            ),
+            TwitterError::Duplicate
+        );
+        assert_eq!(
+            TwitterError::from_response(StatusCode::FORBIDDEN, &empty, "Your account is suspended"),
+            TwitterError::Suspended
+        );
+        assert!(TwitterError::from_response(StatusCode::BAD_GATEWAY, &empty, "").is_retryable());
+        assert!(!TwitterError::from_response(StatusCode::BAD_REQUEST, &empty, "").is_retryable());
+
+        assert_eq!(
+            exhausted_limit(&limited),
+            Some(ExhaustedLimit { app_wide: false, reset: 1700 })
+        );
+        assert_eq!(
+            exhausted_limit(&app_limited),
+            Some(ExhaustedLimit { app_wide: true, reset: 9000 })
+        );
+        assert_eq!(exhausted_limit(&empty), None);
+    }
+}
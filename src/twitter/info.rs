@@ -16,18 +16,41 @@ pub struct UserInfo {
     // pub most_recent_tweet_id: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct TweetResponse {
+    data: TweetData,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TweetData {
+    pub id: String,
+    pub text: String,
+}
+
 impl TwitterClient<'_> {
     pub async fn get_user_info(&self) -> eyre::Result<UserInfo> {
-        let resp = self.client
-            .get(
-            "https://api.twitter.com/2/users/me?user.fields=profile_image_url,most_recent_tweet_id"
-                .to_string(),
-        )
-        .send()
-        .await?;
+        let resp = self
+            .send(
+                self.client
+                    .get(
+                        self.api_url(
+                            "/2/users/me?user.fields=profile_image_url,most_recent_tweet_id",
+                        ),
+                    )
+                    .send(),
+            )
+            .await?;
         let user_info: UserInfoResponse = resp.json().await?;
         let user_info = user_info.data;
         log::info!("Fetched x_info: {:?}", user_info);
         Ok(user_info)
     }
+
+    pub async fn get_tweet(&self, tweet_id: &str) -> eyre::Result<TweetData> {
+        let resp = self
+            .send(self.client.get(self.api_url(&format!("/2/tweets/{}", tweet_id))).send())
+            .await?;
+        let tweet: TweetResponse = resp.json().await?;
+        Ok(tweet.data)
+    }
 }
@@ -0,0 +1,221 @@
+use serde::Deserialize;
+use tokio::time::{sleep, Duration};
+
+use super::builder::TwitterClient;
+
+/// X accepts APPEND segments of up to 5MB.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+const MAX_STATUS_CHECKS: u32 = 60;
+pub const MAX_IMAGES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Gif,
+    Video,
+}
+
+impl MediaKind {
+    /// Largest file X accepts for this kind of media.
+    pub fn max_bytes(&self) -> usize {
+        match self {
+            MediaKind::Image => 5 * 1024 * 1024,
+            MediaKind::Gif => 15 * 1024 * 1024,
+            MediaKind::Video => 512 * 1024 * 1024,
+        }
+    }
+
+    fn category(&self) -> &'static str {
+        match self {
+            MediaKind::Image => "tweet_image",
+            MediaKind::Gif => "tweet_gif",
+            MediaKind::Video => "tweet_video",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaType {
+    pub mime_type: &'static str,
+    pub kind: MediaKind,
+}
+
+/// Detects the media type from the file's magic bytes, ignoring whatever the host claims.
+pub fn sniff_media_type(bytes: &[u8]) -> eyre::Result<MediaType> {
+    let media_type = |mime_type, kind| Ok(MediaType { mime_type, kind });
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        media_type("image/jpeg", MediaKind::Image)
+    } else if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        media_type("image/png", MediaKind::Image)
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        media_type("image/webp", MediaKind::Image)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        media_type("image/gif", MediaKind::Gif)
+    } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        if &bytes[8..12] == b"qt  " {
+            media_type("video/quicktime", MediaKind::Video)
+        } else {
+            media_type("video/mp4", MediaKind::Video)
+        }
+    } else {
+        eyre::bail!("Unsupported media type")
+    }
+}
+
+/// Checks that the files can be attached to one tweet: up to four images, or a single GIF or
+/// video, each within X's size limit.
+pub fn validate_media(media: &[(MediaType, usize)]) -> eyre::Result<()> {
+    for (media_type, size) in media {
+        if *size > media_type.kind.max_bytes() {
+            eyre::bail!(
+                "{} is {} bytes, larger than the {} byte limit",
+                media_type.mime_type,
+                size,
+                media_type.kind.max_bytes()
+            );
+        }
+    }
+    let images = media.iter().filter(|(media_type, _)| media_type.kind == MediaKind::Image);
+    if images.count() < media.len() && media.len() > 1 {
+        eyre::bail!("A GIF or video must be the only media in a tweet");
+    }
+    if media.len() > MAX_IMAGES {
+        eyre::bail!("A tweet can have at most {} images", MAX_IMAGES);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct ProcessingInfo {
+    state: String,
+    check_after_secs: Option<u64>,
+    error: Option<ProcessingError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProcessingError {
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkedUploadResponse {
+    media_id_string: String,
+    processing_info: Option<ProcessingInfo>,
+}
+
+impl TwitterClient<'_> {
+    /// Uploads media of any supported type, using the chunked INIT/APPEND/FINALIZE flow for GIFs
+    /// and videos. Returns the media id.
+    ///
+    /// The v1.1 upload endpoint only accepts OAuth 1.0a, so this fails for OAuth 2.0 clients.
+    pub async fn upload_media_file(&self, media_bytes: Vec<u8>) -> eyre::Result<String> {
+        if self.client.is_oauth2() {
+            eyre::bail!("Media uploads need OAuth 1.0a tokens");
+        }
+        let media_type = sniff_media_type(&media_bytes)?;
+        validate_media(&[(media_type, media_bytes.len())])?;
+        match media_type.kind {
+            MediaKind::Image => self.upload_media(media_bytes, None).await,
+            _ => self.upload_media_chunked(media_bytes, media_type).await,
+        }
+    }
+
+    async fn upload_media_chunked(
+        &self,
+        media_bytes: Vec<u8>,
+        media_type: MediaType,
+    ) -> eyre::Result<String> {
+        let total_bytes = media_bytes.len().to_string();
+        let init: ChunkedUploadResponse = self
+            .send(
+                self.client
+                    .post(self.upload_url())
+                    .form(&[
+                        ("command", "INIT"),
+                        ("total_bytes", total_bytes.as_str()),
+                        ("media_type", media_type.mime_type),
+                        ("media_category", media_type.kind.category()),
+                    ])
+                    .send(),
+            )
+            .await?
+            .json()
+            .await?;
+        let media_id = init.media_id_string;
+        log::info!("Initialized chunked upload of {} bytes as {}", total_bytes, media_id);
+
+        for (segment_index, chunk) in media_bytes.chunks(CHUNK_SIZE).enumerate() {
+            let form = reqwest::multipart::Form::new()
+                .text("command", "APPEND")
+                .text("media_id", media_id.clone())
+                .text("segment_index", segment_index.to_string())
+                .part("media", reqwest::multipart::Part::bytes(chunk.to_vec()));
+            self.send(self.client.post(self.upload_url()).multipart(form).send()).await?;
+        }
+
+        let finalize: ChunkedUploadResponse = self
+            .send(
+                self.client
+                    .post(self.upload_url())
+                    .form(&[("command", "FINALIZE"), ("media_id", media_id.as_str())])
+                    .send(),
+            )
+            .await?
+            .json()
+            .await?;
+        let mut processing_info = finalize.processing_info;
+
+        for _ in 0..MAX_STATUS_CHECKS {
+            let Some(info) = processing_info else {
+                return Ok(media_id);
+            };
+            match info.state.as_str() {
+                "succeeded" => return Ok(media_id),
+                "failed" => {
+                    let message = info.error.and_then(|error| error.message).unwrap_or_default();
+                    eyre::bail!("Media {} failed processing: {}", media_id, message);
+                }
+                _ => {}
+            }
+            sleep(Duration::from_secs(info.check_after_secs.unwrap_or(1))).await;
+            let status: ChunkedUploadResponse = self
+                .send(
+                    self.client
+                        .get(self.upload_url())
+                        .query(&[("command", "STATUS"), ("media_id", media_id.as_str())])
+                        .send(),
+                )
+                .await?
+                .json()
+                .await?;
+            processing_info = status.processing_info;
+        }
+        eyre::bail!("Media {} did not finish processing", media_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_media_type_test() -> eyre::Result<()> {
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0];
+        assert_eq!(sniff_media_type(&png)?.mime_type, "image/png");
+        assert_eq!(sniff_media_type(b"GIF89a....")?.kind, MediaKind::Gif);
+        assert_eq!(sniff_media_type(b"\0\0\0\x18ftypmp42....")?.mime_type, "video/mp4");
+        assert!(sniff_media_type(b"<html></html>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_media_test() {
+        let image = MediaType { mime_type: "image/png", kind: MediaKind::Image };
+        let video = MediaType { mime_type: "video/mp4", kind: MediaKind::Video };
+        assert!(validate_media(&[(image, 1000); 4]).is_ok());
+        assert!(validate_media(&[(image, 1000); 5]).is_err());
+        assert!(validate_media(&[(image, 6 * 1024 * 1024)]).is_err());
+        assert!(validate_media(&[(video, 100 * 1024 * 1024)]).is_ok());
+        assert!(validate_media(&[(video, 1000), (image, 1000)]).is_err());
+    }
+}
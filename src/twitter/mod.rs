@@ -1,7 +1,11 @@
 pub mod auth;
 pub mod builder;
+pub mod error;
 pub mod info;
+pub mod media;
+pub mod oauth2;
 pub mod post;
+pub mod rate_limit;
 pub mod react;
 pub mod tweet;
 
@@ -0,0 +1,208 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::error::TwitterError;
+
+/// Refresh access tokens this long before X says they expire.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// What users grant the enclave. X requires `tweet.read` alongside `tweet.write` to post, and
+/// `offline.access` is what issues a refresh token.
+pub const SCOPES: &[&str] =
+    &["tweet.read", "tweet.write", "like.write", "users.read", "offline.access"];
+
+/// A registered OAuth 2.0 app. Confidential clients also have a secret.
+#[derive(Debug, Clone)]
+pub struct OAuth2Config {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+impl OAuth2Config {
+    /// Reads `X_OAUTH2_CLIENT_ID` and `X_OAUTH2_CLIENT_SECRET`. OAuth 2.0 login is disabled when
+    /// the client id is unset.
+    pub fn from_env() -> Option<Self> {
+        let client_id = std::env::var("X_OAUTH2_CLIENT_ID").ok()?;
+        let client_secret = std::env::var("X_OAUTH2_CLIENT_SECRET").ok();
+        Some(Self { client_id, client_secret })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkceChallenge {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl PkceChallenge {
+    pub fn new() -> Self {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self::from_verifier(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// The S256 challenge for `verifier`.
+    pub fn from_verifier(verifier: String) -> Self {
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        Self { verifier, challenge }
+    }
+}
+
+impl Default for PkceChallenge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// OAuth 2.0 user tokens. X rotates the refresh token on every refresh, so the stored tokens must
+/// be replaced each time.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OAuth2Tokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp (seconds) when the access token expires.
+    pub expires_at: i64,
+    pub scope: String,
+}
+
+impl OAuth2Tokens {
+    pub fn needs_refresh(&self) -> bool {
+        self.expires_at - EXPIRY_MARGIN_SECS <= chrono::Utc::now().timestamp()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: i64,
+    scope: String,
+}
+
+pub fn authorize_url(
+    web_url: &str,
+    config: &OAuth2Config,
+    redirect_uri: &str,
+    state: &str,
+    pkce: &PkceChallenge,
+) -> eyre::Result<String> {
+    let url = url::Url::parse_with_params(
+        &format!("{}/i/oauth2/authorize", web_url),
+        &[
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", SCOPES.join(" ").as_str()),
+            ("state", state),
+            ("code_challenge", pkce.challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )?;
+    Ok(url.to_string())
+}
+
+pub async fn exchange_code(
+    api_url: &str,
+    config: &OAuth2Config,
+    code: String,
+    redirect_uri: String,
+    verifier: String,
+) -> eyre::Result<OAuth2Tokens> {
+    request_tokens(
+        api_url,
+        config,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("code_verifier", verifier.as_str()),
+            ("client_id", config.client_id.as_str()),
+        ],
+    )
+    .await
+}
+
+/// Trades the refresh token for new tokens. A refresh token that X no longer accepts means the
+/// user revoked access, reported as [`TwitterError::AuthRevoked`].
+pub async fn refresh(
+    api_url: &str,
+    config: &OAuth2Config,
+    tokens: &OAuth2Tokens,
+) -> eyre::Result<OAuth2Tokens> {
+    let Some(refresh_token) = &tokens.refresh_token else {
+        return Err(TwitterError::AuthRevoked.into());
+    };
+    request_tokens(
+        api_url,
+        config,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+            ("client_id", config.client_id.as_str()),
+        ],
+    )
+    .await
+}
+
+async fn request_tokens(
+    api_url: &str,
+    config: &OAuth2Config,
+    form: &[(&str, &str)],
+) -> eyre::Result<OAuth2Tokens> {
+    let mut request = reqwest::Client::new().post(format!("{}/2/oauth2/token", api_url)).form(form);
+    if let Some(client_secret) = &config.client_secret {
+        request = request.basic_auth(&config.client_id, Some(client_secret));
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let headers = response.headers().clone();
+        let body = response.text().await?;
+        log::error!("OAuth 2.0 token request failed: {} {}", status, body);
+        // X answers an invalid or already rotated refresh token with a 400.
+        if status == reqwest::StatusCode::BAD_REQUEST &&
+            (body.contains("invalid_request") || body.contains("invalid_grant"))
+        {
+            return Err(TwitterError::AuthRevoked.into());
+        }
+        return Err(TwitterError::from_response(status, &headers, &body).into());
+    }
+    let tokens: TokenResponse = response.json().await?;
+    Ok(OAuth2Tokens {
+        access_token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_at: chrono::Utc::now().timestamp() + tokens.expires_in,
+        scope: tokens.scope,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkce_test() -> eyre::Result<()> {
+        // From RFC 7636, appendix B.
+        let pkce =
+            PkceChallenge::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string());
+        assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+        assert_ne!(PkceChallenge::new().verifier, PkceChallenge::new().verifier);
+
+        let config = OAuth2Config { client_id: "client".to_string(), client_secret: None };
+        let url = url::Url::parse(&authorize_url(
+            "https://twitter.com",
+            &config,
+            "https://tee.example/callback2",
+            "state",
+            &pkce,
+        )?)?;
+        let query = url.query_pairs().into_owned().collect::<std::collections::HashMap<_, _>>();
+        assert_eq!(query["code_challenge"], pkce.challenge);
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert_eq!(query["scope"], "tweet.read tweet.write like.write users.read offline.access");
+        assert_eq!(query["redirect_uri"], "https://tee.example/callback2");
+        Ok(())
+    }
+}
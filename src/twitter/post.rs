@@ -25,11 +25,13 @@ impl TwitterClient<'_> {
         tweet.validate()?;
         let body = serde_json::to_string(&tweet)?;
         let resp = self
-            .client
-            .post("https://api.twitter.com/2/tweets".to_string())
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(body)
-            .send()
+            .send(
+                self.client
+                    .post(self.api_url("/2/tweets"))
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .send(),
+            )
             .await?;
 
         let body = resp.text().await?;
@@ -57,12 +59,7 @@ impl TwitterClient<'_> {
         if let Some(additional_owners) = additional_owners {
             form = form.text("additional_owners", additional_owners.join(","));
         }
-        let resp = self
-            .client
-            .post("https://upload.twitter.com/1.1/media/upload.json".to_string())
-            .multipart(form)
-            .send()
-            .await?;
+        let resp = self.send(self.client.post(self.upload_url()).multipart(form).send()).await?;
         let body = resp.text().await?;
         let media_upload_response: Result<MediaUploadResponse, _> = serde_json::from_str(&body);
         match media_upload_response {
@@ -0,0 +1,59 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use super::error::ExhaustedLimit;
+
+/// Remembers exhausted X rate limits so that requests are held back until the limit resets
+/// instead of being sent only to fail.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    app_blocked_until: Mutex<Option<i64>>,
+    user_blocked_until: Mutex<HashMap<String, i64>>,
+}
+
+impl RateLimiter {
+    /// When requests for `user_key` may be sent again, if they are currently blocked.
+    pub fn blocked_until(&self, user_key: &str) -> Option<i64> {
+        let now = chrono::Utc::now().timestamp();
+        let app = *self.app_blocked_until.lock().unwrap();
+        let user = self.user_blocked_until.lock().unwrap().get(user_key).copied();
+        app.max(user).filter(|reset| *reset > now)
+    }
+
+    pub fn block(&self, user_key: &str, limit: ExhaustedLimit) {
+        if limit.app_wide {
+            let mut app = self.app_blocked_until.lock().unwrap();
+            *app = (*app).max(Some(limit.reset));
+        } else {
+            let now = chrono::Utc::now().timestamp();
+            let mut users = self.user_blocked_until.lock().unwrap();
+            users.retain(|_, reset| *reset > now);
+            let reset = users.entry(user_key.to_string()).or_insert(limit.reset);
+            *reset = (*reset).max(limit.reset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_test() {
+        let limiter = RateLimiter::default();
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(limiter.blocked_until("alice"), None);
+
+        limiter.block("alice", ExhaustedLimit { app_wide: false, reset: now + 60 });
+        assert_eq!(limiter.blocked_until("alice"), Some(now + 60));
+        assert_eq!(limiter.blocked_until("bob"), None);
+
+        limiter.block("bob", ExhaustedLimit { app_wide: true, reset: now + 120 });
+        assert_eq!(limiter.blocked_until("alice"), Some(now + 120));
+        assert_eq!(limiter.blocked_until("carol"), Some(now + 120));
+
+        // Expired limits no longer block.
+        let limiter = RateLimiter::default();
+        limiter.block("alice", ExhaustedLimit { app_wide: false, reset: now - 1 });
+        assert_eq!(limiter.blocked_until("alice"), None);
+    }
+}
@@ -9,24 +9,26 @@ struct LikeTweet {
 
 impl TwitterClient<'_> {
     pub async fn like(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
-        let _ = self
-            .client
-            .post(format!("https://api.twitter.com/2/users/{}/likes", x_id))
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(serde_json::to_string(&LikeTweet { tweet_id })?)
-            .send()
-            .await?;
+        self.send(
+            self.client
+                .post(self.api_url(&format!("/2/users/{}/likes", x_id)))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&LikeTweet { tweet_id })?)
+                .send(),
+        )
+        .await?;
         Ok(())
     }
 
     pub async fn retweet(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
-        let _ = self
-            .client
-            .post(format!("https://api.twitter.com/2/users/{}/retweets", x_id))
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(serde_json::to_string(&LikeTweet { tweet_id })?)
-            .send()
-            .await?;
+        self.send(
+            self.client
+                .post(self.api_url(&format!("/2/users/{}/retweets", x_id)))
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&LikeTweet { tweet_id })?)
+                .send(),
+        )
+        .await?;
         Ok(())
     }
 }
@@ -1,5 +1,49 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
 use serde::Serialize;
 
+use super::media::MAX_IMAGES;
+
+/// X's limit on the weighted length of a tweet.
+pub const MAX_WEIGHTED_LENGTH: usize = 280;
+/// Every URL is shortened to a t.co link of this length.
+const URL_LENGTH: usize = 23;
+/// Code points in these ranges (Latin, general punctuation, ...) weigh 1, all others weigh 2.
+const SINGLE_WEIGHT_RANGES: [(u32, u32); 4] = [(0, 4351), (8192, 8205), (8208, 8223), (8242, 8247)];
+
+fn url_regex() -> &'static Regex {
+    static URL_REGEX: OnceLock<Regex> = OnceLock::new();
+    URL_REGEX.get_or_init(|| {
+        Regex::new(
+            r"(?i)\bhttps?://[^\s]+|\b(?:[a-z0-9-]+\.)+(?:com|net|org|io|xyz|co|me|ai|app|dev|gg|ly)\b(?:/[^\s]*)?",
+        )
+        .expect("Invalid URL regex")
+    })
+}
+
+fn char_weight(c: char) -> usize {
+    let code_point = c as u32;
+    if SINGLE_WEIGHT_RANGES.iter().any(|(start, end)| (*start..=*end).contains(&code_point)) {
+        1
+    } else {
+        2
+    }
+}
+
+/// Length of the text as X counts it: URLs count as a t.co link and CJK characters and emoji
+/// count double. Emoji sequences are counted per code point, which may overestimate them.
+pub fn weighted_length(text: &str) -> usize {
+    let mut length = 0;
+    let mut last_end = 0;
+    for url in url_regex().find_iter(text) {
+        length += text[last_end..url.start()].chars().map(char_weight).sum::<usize>();
+        length += URL_LENGTH;
+        last_end = url.end();
+    }
+    length + text[last_end..].chars().map(char_weight).sum::<usize>()
+}
+
 #[derive(Debug, Serialize)]
 struct Reply {
     in_reply_to_tweet_id: String,
@@ -28,6 +72,14 @@ impl Tweet {
         if self.text.is_empty() {
             eyre::bail!("Tweet text cannot be empty");
         }
+        let length = weighted_length(&self.text);
+        if length > MAX_WEIGHTED_LENGTH {
+            eyre::bail!(
+                "Tweet is {} characters long, over the {} limit",
+                length,
+                MAX_WEIGHTED_LENGTH
+            );
+        }
         if self.quote_tweet_id.is_some() && self.reply.is_some() {
             eyre::bail!("Tweet cannot be both a quote and a reply");
         }
@@ -35,6 +87,9 @@ impl Tweet {
             if media.media_ids.is_empty() {
                 eyre::bail!("Media IDs cannot be empty");
             }
+            if media.media_ids.len() > MAX_IMAGES {
+                eyre::bail!("Tweet cannot have more than {} media", MAX_IMAGES);
+            }
         }
         Ok(())
     }
@@ -51,3 +106,40 @@ impl Tweet {
         self.media = Some(Media { media_ids });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_length_test() {
+        assert_eq!(weighted_length("gm"), 2);
+        assert_eq!(weighted_length("gm https://example.com/a/very/long/path?with=query"), 3 + 23);
+        assert_eq!(weighted_length("see teleport.xyz"), 4 + 23);
+        assert_eq!(weighted_length("こんにちは"), 10);
+        assert_eq!(weighted_length("café — ok"), 9);
+        assert_eq!(weighted_length("🚀"), 2);
+    }
+
+    #[test]
+    fn validate_test() {
+        assert!(Tweet::new("gm".to_string()).validate().is_ok());
+        assert!(Tweet::new(String::new()).validate().is_err());
+        assert!(Tweet::new("a".repeat(280)).validate().is_ok());
+        assert!(Tweet::new("a".repeat(281)).validate().is_err());
+        assert!(Tweet::new("字".repeat(140)).validate().is_ok());
+        assert!(Tweet::new("字".repeat(141)).validate().is_err());
+        // A long URL still counts as 23.
+        let url = format!("https://example.com/{}", "a".repeat(300));
+        assert!(Tweet::new(format!("{} {}", "a".repeat(256), url)).validate().is_ok());
+
+        let mut tweet = Tweet::new("gm".to_string());
+        tweet.set_media_ids(vec!["1".to_string(); 5]);
+        assert!(tweet.validate().is_err());
+        tweet.set_media_ids(vec!["1".to_string(); 4]);
+        assert!(tweet.validate().is_ok());
+        tweet.set_reply_tweet_id("1".to_string());
+        tweet.set_quote_tweet_id("2".to_string());
+        assert!(tweet.validate().is_err());
+    }
+}
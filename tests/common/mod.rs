@@ -0,0 +1,61 @@
+//! Local servers the integration tests run against.
+
+use std::{
+    net::TcpListener,
+    process::{Child, Command},
+    time::Duration,
+};
+
+pub const CONSUMER_KEY: &str = "mock-consumer-key";
+pub const CONSUMER_SECRET: &str = "mock-consumer-secret";
+pub const OAUTH2_CLIENT_ID: &str = "mock-client-id";
+pub const OAUTH2_CLIENT_SECRET: &str = "mock-client-secret";
+
+/// A server process, killed when dropped.
+pub struct LocalServer {
+    child: Child,
+    pub base_url: String,
+}
+
+impl LocalServer {
+    /// Runs `command` and waits until it accepts connections on `port`.
+    pub async fn spawn(command: &mut Command, port: u16) -> eyre::Result<Self> {
+        let addr = format!("127.0.0.1:{}", port);
+        let server = Self { child: command.spawn()?, base_url: format!("http://{}", addr) };
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+                return Ok(server);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        eyre::bail!("{:?} did not start", command)
+    }
+
+    /// The `mock_x` server, with an OAuth 2.0 client.
+    pub async fn mock_x() -> eyre::Result<Self> {
+        let port = free_port()?;
+        let mut command = Command::new(env!("CARGO_BIN_EXE_mock_x"));
+        command
+            .env("MOCK_X_ADDR", format!("127.0.0.1:{}", port))
+            .env("TWITTER_CONSUMER_KEY", CONSUMER_KEY)
+            .env("TWITTER_CONSUMER_SECRET", CONSUMER_SECRET)
+            .env("X_OAUTH2_CLIENT_ID", OAUTH2_CLIENT_ID)
+            .env("X_OAUTH2_CLIENT_SECRET", OAUTH2_CLIENT_SECRET);
+        Self::spawn(&mut command, port).await
+    }
+
+    /// What was posted to the `mock_x` server.
+    pub async fn mock_x_state(&self) -> eyre::Result<serde_json::Value> {
+        Ok(reqwest::get(format!("{}/mock/state", self.base_url)).await?.json().await?)
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+pub fn free_port() -> eyre::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
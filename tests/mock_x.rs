@@ -0,0 +1,89 @@
+//! Runs the X side of login, mint and redeem against the `mock_x` server.
+
+use teleport::twitter::{
+    builder::{TwitterBuilder, XApiUrls},
+    error::TwitterError,
+    tweet::Tweet,
+};
+
+use common::{LocalServer, CONSUMER_KEY, CONSUMER_SECRET};
+
+mod common;
+
+const PNG: [u8; 12] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+fn twitter_error(result: eyre::Result<impl std::fmt::Debug>) -> TwitterError {
+    result.unwrap_err().downcast::<TwitterError>().expect("Expected a TwitterError")
+}
+
+#[tokio::test]
+async fn mock_x_flow_test() -> eyre::Result<()> {
+    let server = LocalServer::mock_x().await?;
+    let twitter_builder =
+        TwitterBuilder::new(CONSUMER_KEY.to_string(), CONSUMER_SECRET.to_string())
+            .with_urls(XApiUrls::single_host(&server.base_url));
+
+    // Login: request token, user approval, access token.
+    let callback_url = "http://localhost/callback?address=0xabc".to_string();
+    let request_token = twitter_builder.request_oauth_token(callback_url).await?;
+    let no_redirects =
+        reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build()?;
+    let approval =
+        no_redirects.get(twitter_builder.authenticate_url(&request_token.token)).send().await?;
+    let location = url::Url::parse(approval.headers()["location"].to_str()?)?;
+    let verifier = location
+        .query_pairs()
+        .find(|(key, _)| key == "oauth_verifier")
+        .map(|(_, value)| value.to_string())
+        .expect("Callback has no oauth_verifier");
+    let access_tokens = twitter_builder
+        .authorize_token(request_token.token.clone(), request_token.secret, verifier)
+        .await?;
+    let client = twitter_builder.with_auth(access_tokens.clone());
+
+    // Mint reads the user's profile.
+    let user_info = client.get_user_info().await?;
+    assert_eq!(user_info.id, "1000");
+    assert_eq!(user_info.username, "mock_user");
+
+    // Redeem posts a tweet with an image, a GIF through the chunked upload, and reactions.
+    let image_id = client.upload_media_file(PNG.to_vec()).await?;
+    let mut tweet = Tweet::new("gm from the mock".to_string());
+    tweet.set_media_ids(vec![image_id]);
+    let tweet_id = client.raw_tweet(tweet).await?;
+    assert_eq!(client.get_tweet(&tweet_id).await?.text, "gm from the mock");
+
+    let gif_id = client.upload_media_file(b"GIF89a-not-really-a-gif".to_vec()).await?;
+    let mut tweet = Tweet::new("gn from the mock".to_string());
+    tweet.set_media_ids(vec![gif_id]);
+    tweet.set_reply_tweet_id(tweet_id.clone());
+    client.raw_tweet(tweet).await?;
+
+    client.like(user_info.id.clone(), tweet_id.clone()).await?;
+    client.retweet(user_info.id.clone(), tweet_id.clone()).await?;
+
+    let state = server.mock_x_state().await?;
+    assert_eq!(state["tweets"].as_array().unwrap().len(), 2);
+    assert_eq!(state["tweets"][1]["reply"]["in_reply_to_tweet_id"], tweet_id);
+    assert_eq!(state["media_ids"].as_array().unwrap().len(), 2);
+    assert_eq!(state["likes"][0], tweet_id);
+    assert_eq!(state["retweets"][0], tweet_id);
+
+    // Failures are classified like the real API's.
+    let duplicate = client.raw_tweet(Tweet::new("gm from the mock".to_string())).await;
+    assert_eq!(twitter_error(duplicate), TwitterError::Duplicate);
+
+    let wrong_secret = TwitterBuilder::new(CONSUMER_KEY.to_string(), "wrong".to_string())
+        .with_urls(XApiUrls::single_host(&server.base_url));
+    let bad_signature = wrong_secret.with_auth(access_tokens.clone()).get_user_info().await;
+    assert_eq!(twitter_error(bad_signature), TwitterError::AuthRevoked);
+
+    reqwest::Client::new()
+        .post(format!("{}/mock/revoke", server.base_url))
+        .query(&[("token", &access_tokens.token)])
+        .send()
+        .await?
+        .error_for_status()?;
+    assert_eq!(twitter_error(client.get_user_info().await), TwitterError::AuthRevoked);
+    Ok(())
+}
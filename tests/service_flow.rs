@@ -0,0 +1,354 @@
+//! Runs login, mint, redeem and the redeemed tweet through the service's endpoints, event handler
+//! and job worker, offline: the chain is a local `anvil`, X is the `mock_x` server and the
+//! frontend's database is a scratch Postgres database at `TEST_DATABASE_URL`, whose tables are
+//! recreated. Run it with `cargo test --test service_flow -- --ignored`.
+
+use std::{process::Command, str::FromStr, sync::Arc, time::Duration};
+
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::Provider,
+    rpc::types::Filter,
+    signers::{local::PrivateKeySigner, Signer},
+    sol_types::SolStruct,
+};
+use reqwest::header;
+use serde_json::{json, Value};
+use teleport::{
+    actions::{
+        checkpoint::Checkpoint,
+        jobs::{run_job_worker, JobQueue, RetryConfig},
+        nft::NFT,
+        subscriber::{Confirmation, EventHandler},
+        tx_manager::{TxConfig, TxManager},
+        wallet::{get_provider, WalletProvider},
+    },
+    content::RedeemContent,
+    db::{client_db::ClientDB, sqlite::SqliteDB, TeleportDB, TokenType},
+    eip712::{teleport_domain, RedeemAuthorization},
+    endpoints::{router, SharedState},
+    policy::{mock::MockChecker, PolicyChecker},
+    twitter::{
+        builder::{TwitterBuilder, XApiUrls},
+        oauth2::OAuth2Config,
+    },
+};
+use tokio::{sync::Mutex, time::sleep};
+use url::Url;
+
+use common::{
+    free_port, LocalServer, CONSUMER_KEY, CONSUMER_SECRET, OAUTH2_CLIENT_ID, OAUTH2_CLIENT_SECRET,
+};
+
+mod common;
+
+/// The first two of anvil's default accounts.
+const ENCLAVE_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+const OWNER_KEY: &str = "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+const TEE_HOST: &str = "tee.test";
+const APP_URL: &str = "http://app.test";
+const POLICY: &str = "Only post about the weather.";
+
+/// The frontend's tables, as far as the service uses them, before the service's migrations.
+const FRONTEND_SCHEMA: &str = r#"
+DROP TABLE IF EXISTS "User", "NftIndex", "RedeemedIndex";
+CREATE TABLE "User" (
+    "id" TEXT PRIMARY KEY,
+    "haveBeenRedeemed" INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE "NftIndex" (
+    "id" TEXT PRIMARY KEY,
+    "userId" TEXT NOT NULL,
+    "tokenId" INTEGER UNIQUE,
+    "twitterName" TEXT NOT NULL,
+    "twitterUserName" TEXT NOT NULL,
+    "twitterPfpUrl" TEXT NOT NULL,
+    "safeguard" TEXT NOT NULL,
+    "updatedAt" TIMESTAMP NOT NULL
+);
+CREATE TABLE "RedeemedIndex" (
+    "id" TEXT PRIMARY KEY,
+    "creatorUserId" TEXT NOT NULL,
+    "tokenId" INTEGER NOT NULL,
+    "tweetId" TEXT NOT NULL,
+    "twitterUserName" TEXT NOT NULL,
+    "safeguard" TEXT NOT NULL,
+    "content" TEXT NOT NULL
+);
+"#;
+
+/// Where a redirect sends the browser.
+fn location(response: &reqwest::Response) -> eyre::Result<Url> {
+    eyre::ensure!(response.status().is_redirection(), "Expected a redirect: {}", response.status());
+    Ok(Url::parse(response.headers()[header::LOCATION].to_str()?)?)
+}
+
+/// The local service's URL for a redirect to the enclave's public host.
+fn on_service(url: &Url, service_url: &str) -> String {
+    format!("{}{}?{}", service_url, url.path(), url.query().unwrap_or_default())
+}
+
+fn session_cookie(response: &reqwest::Response) -> eyre::Result<String> {
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str()?;
+    Ok(set_cookie.split(';').next().unwrap_or_default().to_string())
+}
+
+/// Hands the contract's logs to the event handler, as the subscriber does, and processes them up
+/// to the head. Logs that were processed before are skipped.
+async fn deliver_logs<A: TeleportDB>(
+    handler: &EventHandler<A>,
+    provider: &WalletProvider,
+    nft_address: Address,
+) -> eyre::Result<()> {
+    let head = provider.get_block_number().await?;
+    let filter = Filter::new().address(nft_address).from_block(0).to_block(head);
+    for log in provider.get_logs(&filter).await? {
+        handler.on_log(log).await?;
+    }
+    handler.release_confirmed(head).await
+}
+
+async fn wait_for_receipt(provider: &WalletProvider, tx_hash: B256) -> eyre::Result<()> {
+    for _ in 0..100 {
+        if provider.get_transaction_receipt(tx_hash).await?.is_some() {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    eyre::bail!("Transaction {} was not mined", tx_hash)
+}
+
+async fn wait_for_posted(
+    http: &reqwest::Client,
+    service_url: &str,
+    token_id: &str,
+) -> eyre::Result<Value> {
+    for _ in 0..100 {
+        let redemption: Value = http
+            .get(format!("{}/redemptionStatus", service_url))
+            .query(&[("token_id", token_id)])
+            .send()
+            .await?
+            .json()
+            .await?;
+        if redemption["status"] == "posted" {
+            return Ok(redemption);
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    eyre::bail!("NFT {} redemption was not posted", token_id)
+}
+
+#[tokio::test]
+#[ignore = "needs anvil and a scratch Postgres database at TEST_DATABASE_URL"]
+async fn service_flow_test() -> eyre::Result<()> {
+    let database_url = std::env::var("TEST_DATABASE_URL")?;
+    let mock_x = LocalServer::mock_x().await?;
+    let anvil_port = free_port()?;
+    let anvil = LocalServer::spawn(
+        Command::new("anvil").args(["--port", &anvil_port.to_string(), "--silent"]),
+        anvil_port,
+    )
+    .await?;
+    let rpc_url = anvil.base_url.clone();
+
+    // The enclave wallet deploys the NFT contract and mints with it.
+    let enclave = PrivateKeySigner::from_str(ENCLAVE_KEY)?;
+    let owner = PrivateKeySigner::from_str(OWNER_KEY)?;
+    let address = owner.address().to_string();
+    let provider = get_provider(rpc_url.clone(), enclave.clone().into());
+    let nft = NFT::deploy(
+        provider.clone(),
+        "Teleport".to_string(),
+        "TELE".to_string(),
+        enclave.address(),
+    )
+    .await?;
+    if !nft.isWhitelisted(enclave.address()).call().await?._0 {
+        nft.whitelistMinter(enclave.address()).send().await?.get_receipt().await?;
+    }
+    let nft_address = *nft.address();
+    std::env::set_var("NFT_ADDRESS", nft_address.to_string());
+    std::env::set_var("MEDIA_LOCAL_HOSTS", "127.0.0.1");
+
+    let client_db = ClientDB::new(database_url);
+    client_db.client().await?.batch_execute(FRONTEND_SCHEMA).await?;
+    client_db.migrate().await?;
+    client_db
+        .client()
+        .await?
+        .execute("INSERT INTO \"User\" (\"id\") VALUES ($1)", &[&address])
+        .await?;
+
+    let scratch = std::env::temp_dir().join(format!("teleport-flow-{}", cuid::cuid2()));
+    std::fs::create_dir_all(&scratch)?;
+    let db = Arc::new(Mutex::new(SqliteDB::open_in_memory()?));
+    let job_queue = Arc::new(JobQueue::open(scratch.join("jobs.json"), RetryConfig::default())?);
+    let policy_checker: Arc<dyn PolicyChecker> = Arc::new(MockChecker::default());
+    let twitter_builder =
+        TwitterBuilder::new(CONSUMER_KEY.to_string(), CONSUMER_SECRET.to_string())
+            .with_urls(XApiUrls::single_host(&mock_x.base_url))
+            .with_oauth2(Some(OAuth2Config {
+                client_id: OAUTH2_CLIENT_ID.to_string(),
+                client_secret: Some(OAUTH2_CLIENT_SECRET.to_string()),
+            }));
+    let tx_config = TxConfig { poll_interval: Duration::from_millis(100), ..Default::default() };
+    let eip712_domain =
+        teleport_domain(provider.get_chain_id().await?, nft_address, enclave.address());
+    let shared_state = SharedState {
+        db: db.clone(),
+        signer: enclave.clone(),
+        app_url: APP_URL.to_string(),
+        tee_url: TEE_HOST.to_string(),
+        twitter_builder: twitter_builder.clone(),
+        tx_manager: Arc::new(TxManager::new(
+            get_provider(rpc_url.clone(), enclave.clone().into()),
+            enclave.address(),
+            tx_config,
+        )),
+        client_db: client_db.clone(),
+        eip712_domain: eip712_domain.clone(),
+        rpc_url: rpc_url.clone(),
+        policy_checker: policy_checker.clone(),
+        job_queue: job_queue.clone(),
+        subscriber_status: Default::default(),
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let service_url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move { axum::serve(listener, router(shared_state)).await });
+    tokio::spawn(run_job_worker(
+        job_queue.clone(),
+        db.clone(),
+        client_db.clone(),
+        twitter_builder,
+        policy_checker,
+    ));
+    let event_handler = EventHandler::new(
+        db.clone(),
+        client_db.clone(),
+        job_queue,
+        rpc_url,
+        Arc::new(Checkpoint::open(scratch.join("checkpoint.json"))?),
+        Confirmation::Depth(0),
+    );
+    let http = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build()?;
+
+    // Login with OAuth 1.0a: the service sends the user to X, which sends them back to the
+    // enclave's callback, which links the account for the frontend.
+    let response =
+        http.get(format!("{}/new", service_url)).query(&[("address", &address)]).send().await?;
+    let response = http.get(location(&response)?).send().await?;
+    let response = http.get(on_service(&location(&response)?, &service_url)).send().await?;
+    assert!(location(&response)?.as_str().starts_with(&format!("{}/create?sig=", APP_URL)));
+    let session = session_cookie(&response)?;
+
+    // Mint, then index the token from its NewTokenData event.
+    let minted: Value = http
+        .post(format!("{}/mint", service_url))
+        .header(header::REFERER, format!("https://{}/approve", TEE_HOST))
+        .header(header::COOKIE, session)
+        .json(&json!({ "address": address, "policy": POLICY, "wait": true }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let token_id = minted["token_id"].as_str().unwrap().to_string();
+    let nft_id = minted["nft_id"].as_str().unwrap().to_string();
+    deliver_logs(&event_handler, &provider, nft_address).await?;
+    let token_owner = client_db.get_token_owner(token_id.clone()).await?;
+    assert_eq!(token_owner.user_id, address);
+    assert_eq!(token_owner.twitter_user_name, "@mock_user");
+    assert_eq!(client_db.get_token_policy(token_id.clone()).await?, POLICY);
+
+    // Login again with OAuth 2.0, so that the tweet is posted with a bearer token and its media
+    // is uploaded with the OAuth 1.0a tokens.
+    let response = http
+        .get(format!("{}/newOAuth2", service_url))
+        .query(&[("address", &address)])
+        .send()
+        .await?;
+    let response = http.get(location(&response)?).send().await?;
+    let response = http.get(on_service(&location(&response)?, &service_url)).send().await?;
+    assert!(location(&response)?.as_str().starts_with(&format!("{}/create?sig=", APP_URL)));
+
+    // Redeem with an image, signed by the owner.
+    let content = serde_json::to_string(&RedeemContent {
+        media_urls: vec![format!("{}/mock/media/sunny.png", mock_x.base_url)],
+        ..RedeemContent::new("Sunny all day".to_string())
+    })?;
+    let nonce: Value = http
+        .get(format!("{}/redeemNonce", service_url))
+        .query(&[("address", &address)])
+        .send()
+        .await?
+        .json()
+        .await?;
+    let nonce = nonce["nonce"].as_u64().unwrap();
+    let deadline = chrono::Utc::now().timestamp() as u64 + 600;
+    let authorization = RedeemAuthorization::new(
+        U256::from_str(&token_id)?,
+        &content,
+        TokenType::Tweet.as_u8(),
+        nonce,
+        deadline,
+    );
+    let signature = owner.sign_hash(&authorization.eip712_signing_hash(&eip712_domain)).await?;
+    let redeemed: Value = http
+        .post(format!("{}/redeem", service_url))
+        .json(&json!({
+            "nft_id": nft_id,
+            "content": content,
+            "deadline": deadline,
+            "nonce": nonce,
+            "signature": alloy::hex::encode_prefixed(signature.as_bytes()),
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(redeemed["verdict"]["safe"], true);
+    let tx_hash = B256::from_str(redeemed["hash"].as_str().unwrap())?;
+
+    // The RedeemTweet event queues the tweet, which the job worker posts.
+    wait_for_receipt(&provider, tx_hash).await?;
+    deliver_logs(&event_handler, &provider, nft_address).await?;
+    let redemption = wait_for_posted(&http, &service_url, &token_id).await?;
+    let tweet_id = redemption["tweet_id"].as_str().unwrap().to_string();
+
+    let state = mock_x.mock_x_state().await?;
+    assert_eq!(state["tweets"].as_array().unwrap().len(), 1);
+    assert_eq!(state["tweets"][0]["id"], tweet_id);
+    assert_eq!(state["tweets"][0]["text"], "Sunny all day");
+    assert_eq!(state["tweets"][0]["media"]["media_ids"], state["media_ids"]);
+    assert_eq!(state["media_ids"].as_array().unwrap().len(), 1);
+
+    let client = client_db.client().await?;
+    let token_id_int: i32 = token_id.parse()?;
+    let row = client
+        .query_one(
+            "SELECT \"tweetId\", \"status\", \"tokenType\", \"content\" FROM \"RedeemedIndex\" WHERE \"tokenId\" = $1",
+            &[&token_id_int],
+        )
+        .await?;
+    assert_eq!(row.get::<_, String>(0), tweet_id);
+    assert_eq!(row.get::<_, String>(1), "posted");
+    assert_eq!(row.get::<_, String>(2), "tweet");
+    assert_eq!(row.get::<_, String>(3), "Sunny all day");
+    let row = client
+        .query_one("SELECT \"haveBeenRedeemed\" FROM \"User\" WHERE \"id\" = $1", &[&address])
+        .await?;
+    assert_eq!(row.get::<_, i32>(0), 1);
+    let rows =
+        client.query("SELECT 1 FROM \"NftIndex\" WHERE \"tokenId\" = $1", &[&token_id_int]).await?;
+    assert!(rows.is_empty());
+
+    // Replaying the events neither queues nor posts anything again.
+    deliver_logs(&event_handler, &provider, nft_address).await?;
+    assert_eq!(mock_x.mock_x_state().await?["tweets"].as_array().unwrap().len(), 1);
+
+    drop(anvil);
+    std::fs::remove_dir_all(scratch)?;
+    Ok(())
+}